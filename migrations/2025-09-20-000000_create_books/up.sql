@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS books (
+  id             UUID PRIMARY KEY,
+  removed_at_utc TIMESTAMP WITHOUT TIME ZONE NULL,
+
+  title          VARCHAR(256) NOT NULL,
+  genre          VARCHAR(256) NOT NULL,
+  page_count     INTEGER NOT NULL
+);
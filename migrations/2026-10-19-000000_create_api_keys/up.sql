@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS api_keys (
+  id             UUID PRIMARY KEY,
+  created_at_utc TIMESTAMP WITHOUT TIME ZONE NOT NULL,
+  revoked_at_utc TIMESTAMP WITHOUT TIME ZONE NULL,
+
+  name           VARCHAR(256) NOT NULL,
+  key_hash       BYTEA NOT NULL UNIQUE,
+  scopes         TEXT[] NOT NULL
+);
+-- Books of API keys are owned by the name of the key, so no two keys that are
+-- not revoked may share it.
+CREATE UNIQUE INDEX IF NOT EXISTS api_keys_name_not_revoked ON api_keys (name) WHERE revoked_at_utc IS NULL;
@@ -0,0 +1,5 @@
+-- Owners are namespaced by how they authenticated, i.e. `key:<name>` for API
+-- keys, `jwt:<issuer>|<subject>` for JWTs and `admin:token` for the admin
+-- token. Books created before owners were recorded have no owner, and can only
+-- be changed by admins.
+ALTER TABLE books ADD COLUMN IF NOT EXISTS owner VARCHAR(256) NULL;
@@ -0,0 +1,15 @@
+-- First response to each request carrying header `Idempotency-Key`, replayed
+-- to retries of the same request until it expires.
+CREATE TABLE IF NOT EXISTS idempotency_keys (
+  principal       VARCHAR(256) NOT NULL,
+  key             VARCHAR(256) NOT NULL,
+  created_at_utc  TIMESTAMP WITHOUT TIME ZONE NOT NULL,
+  expires_at_utc  TIMESTAMP WITHOUT TIME ZONE NOT NULL,
+
+  request_hash    BYTEA NOT NULL,
+  response_status SMALLINT NOT NULL,
+  response_body   BYTEA NOT NULL,
+
+  PRIMARY KEY (principal, key)
+);
+CREATE INDEX IF NOT EXISTS idempotency_keys_expires_at_utc ON idempotency_keys (expires_at_utc);
@@ -0,0 +1,113 @@
+//! Logging of the SQL statements executed by the database actor. Bound values,
+//! e.g. titles supplied by clients, are kept out of the logs unless the
+//! [`Policy`] allows them.
+
+/// How statements are rendered in debug and slow query log records.
+#[derive(Clone)]
+pub enum Mode {
+    /// SQL with placeholders `$1`, `$2` etc., without the bound values.
+    Placeholders,
+    /// SQL with placeholders, followed by the bound values by column, except
+    /// that values of the listed columns are replaced with `<redacted>`.
+    /// Statements affecting several rows list the values of each row in turn.
+    Redacted { columns: Vec<String> },
+    /// SQL followed by all bound values, as rendered by `diesel::debug_query`.
+    Full,
+}
+
+#[derive(Clone)]
+pub struct Policy {
+    pub mode: Mode,
+    /// Statements taking at least this long are logged at level warn along
+    /// with their duration. `None` disables slow query logging.
+    pub slow_threshold: Option<std::time::Duration>,
+}
+
+impl Policy {
+    /// Reads the following environment variables:
+    ///
+    /// - `DB_QUERY_LOG`: `placeholders` (default), `redacted` or `full`.
+    /// - `DB_QUERY_LOG_REDACTED_COLUMNS`: Comma separated columns whose values
+    ///   are redacted in mode `redacted`. Defaults to `title,key_hash,owner`.
+    /// - `DB_SLOW_QUERY_MS`: Threshold of slow query logging in milliseconds.
+    ///   Unset by default.
+    pub fn from_env() -> Result<Self, String> {
+        let mode: Mode = match std::env::var("DB_QUERY_LOG").as_deref() {
+            Err(std::env::VarError::NotPresent) | Ok("placeholders") => Mode::Placeholders,
+            Ok("redacted") => Mode::Redacted {
+                columns: match std::env::var("DB_QUERY_LOG_REDACTED_COLUMNS") {
+                    Ok(value) => value
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|column| !column.is_empty())
+                        .map(str::to_owned)
+                        .collect(),
+                    Err(std::env::VarError::NotPresent) => {
+                        vec!["title".to_owned(), "key_hash".to_owned(), "owner".to_owned()]
+                    }
+                    Err(err) => return Err(format!("Invalid DB_QUERY_LOG_REDACTED_COLUMNS: {err}")),
+                },
+            },
+            Ok("full") => Mode::Full,
+            Ok(other) => {
+                return Err(format!(
+                    "Invalid DB_QUERY_LOG {other:?}, expected \"placeholders\", \"redacted\" or \"full\""
+                ));
+            }
+            Err(err) => return Err(format!("Invalid DB_QUERY_LOG: {err}")),
+        };
+
+        let slow_threshold: Option<std::time::Duration> =
+            crate::env::parse("DB_SLOW_QUERY_MS")?.map(std::time::Duration::from_millis);
+
+        Ok(Self { mode, slow_threshold })
+    }
+
+    /// Renders the statement according to the mode. `binds` pairs the values
+    /// bound to the statement with the columns they're bound to.
+    pub fn render<Q>(&self, query: &Q, binds: &[(&str, &dyn std::fmt::Debug)]) -> String
+    where
+        Q: diesel::query_builder::QueryFragment<diesel::pg::Pg>,
+    {
+        use diesel::query_builder::QueryBuilder;
+
+        if let Mode::Full = self.mode {
+            return diesel::debug_query::<diesel::pg::Pg, _>(query).to_string();
+        }
+
+        let mut query_builder: diesel::pg::PgQueryBuilder = diesel::pg::PgQueryBuilder::default();
+        let sql: String = match query.to_sql(&mut query_builder, &diesel::pg::Pg) {
+            Ok(()) => query_builder.finish(),
+            Err(err) => format!("<unrenderable statement: {err}>"),
+        };
+
+        match &self.mode {
+            Mode::Placeholders | Mode::Full => sql,
+            Mode::Redacted { columns } => {
+                let rendered_binds: Vec<String> = binds
+                    .iter()
+                    .map(|(column, value)| {
+                        if columns.iter().any(|redacted| redacted == column) {
+                            format!("{column}=<redacted>")
+                        } else {
+                            format!("{column}={value:?}")
+                        }
+                    })
+                    .collect();
+                format!("{sql} -- binds: [{}]", rendered_binds.join(", "))
+            }
+        }
+    }
+
+    /// Logs the statement if it took at least as long as the threshold.
+    pub fn observe(&self, query_name: &str, statement: &str, elapsed: std::time::Duration) {
+        if let Some(threshold) = self.slow_threshold
+            && elapsed >= threshold
+        {
+            log::warn!(
+                duration_ms = elapsed.as_secs_f64() * 1000.0;
+                "Slow query {query_name} took {elapsed:?}: {statement}"
+            );
+        }
+    }
+}
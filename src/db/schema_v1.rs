@@ -8,6 +8,9 @@ diesel::table! {
         // TIMESTAMP WITHOUT TIME ZONE NULL
         removed_at_utc -> Nullable<Timestamp>,
 
+        // VARCHAR(256) NULL
+        owner -> Nullable<Varchar>,
+
         // VARCHAR(256) NOT NULL
         title -> Varchar,
 
@@ -29,6 +32,10 @@ pub struct Book {
     pub id: uuid::Uuid,
     /// Metadata: `TIMESTAMP WITHOUT TIME ZONE NULL`.
     pub removed_at_utc: Option<chrono::NaiveDateTime>,
+    /// Metadata: `VARCHAR(256) NULL`, identity of the principal that created
+    /// the book, see [`crate::web::auth::Principal::owner`]. `None` for books
+    /// created before owners were recorded.
+    pub owner: Option<String>,
 
     /// `VARCHAR(256) NOT NULL`
     pub title: String,
@@ -37,3 +44,96 @@ pub struct Book {
     /// `INTEGER NOT NULL` (4-byte signed integer, i.e. akin to `i32`)
     pub page_count: i32,
 }
+
+diesel::table! {
+    api_keys (id) {
+        // UUID PRIMARY KEY
+        id -> Uuid,
+
+        // TIMESTAMP WITHOUT TIME ZONE NOT NULL
+        created_at_utc -> Timestamp,
+
+        // TIMESTAMP WITHOUT TIME ZONE NULL
+        revoked_at_utc -> Nullable<Timestamp>,
+
+        // VARCHAR(256) NOT NULL
+        name -> Varchar,
+
+        // BYTEA NOT NULL UNIQUE
+        key_hash -> Bytea,
+
+        // TEXT[] NOT NULL
+        scopes -> Array<Text>,
+    }
+}
+
+/// Key for authenticating to the HTTP API. Only a hash of the key itself is
+/// stored, so that the keys can't be recovered from the database.
+#[derive(diesel::Queryable, diesel::Identifiable, diesel::Selectable, diesel::Insertable, Debug, PartialEq, Clone)]
+#[diesel(table_name = api_keys)]
+pub struct ApiKey {
+    /// Metadata: `UUID PRIMARY KEY`.
+    pub id: uuid::Uuid,
+    /// Metadata: `TIMESTAMP WITHOUT TIME ZONE NOT NULL`.
+    pub created_at_utc: chrono::NaiveDateTime,
+    /// Metadata: `TIMESTAMP WITHOUT TIME ZONE NULL`.
+    pub revoked_at_utc: Option<chrono::NaiveDateTime>,
+
+    /// `VARCHAR(256) NOT NULL`, describing the holder of the key.
+    pub name: String,
+    /// `BYTEA NOT NULL UNIQUE`, SHA-256 of the key.
+    pub key_hash: Vec<u8>,
+    /// `TEXT[] NOT NULL`, e.g. `books:read`.
+    pub scopes: Vec<String>,
+}
+
+diesel::table! {
+    idempotency_keys (principal, key) {
+        // VARCHAR(256) NOT NULL
+        principal -> Varchar,
+
+        // VARCHAR(256) NOT NULL
+        key -> Varchar,
+
+        // TIMESTAMP WITHOUT TIME ZONE NOT NULL
+        created_at_utc -> Timestamp,
+
+        // TIMESTAMP WITHOUT TIME ZONE NOT NULL
+        expires_at_utc -> Timestamp,
+
+        // BYTEA NOT NULL
+        request_hash -> Bytea,
+
+        // SMALLINT NOT NULL
+        response_status -> SmallInt,
+
+        // BYTEA NOT NULL
+        response_body -> Bytea,
+    }
+}
+
+/// First response to a request carrying header `Idempotency-Key`, replayed to
+/// retries of the same request. `PRIMARY KEY (principal, key)`, as each
+/// principal chooses its keys independently of the others.
+#[derive(diesel::Queryable, diesel::Identifiable, diesel::Selectable, diesel::Insertable, Debug, PartialEq, Clone)]
+#[diesel(table_name = idempotency_keys, primary_key(principal, key))]
+pub struct IdempotencyKey {
+    /// `VARCHAR(256) NOT NULL`, identity of the principal that sent the
+    /// request, see [`crate::web::auth::Principal::owner`].
+    pub principal: String,
+    /// `VARCHAR(256) NOT NULL`, value of header `Idempotency-Key`.
+    pub key: String,
+    /// Metadata: `TIMESTAMP WITHOUT TIME ZONE NOT NULL`.
+    pub created_at_utc: chrono::NaiveDateTime,
+    /// Metadata: `TIMESTAMP WITHOUT TIME ZONE NOT NULL`, after which the
+    /// record is deleted and the key may be used for another request.
+    pub expires_at_utc: chrono::NaiveDateTime,
+
+    /// `BYTEA NOT NULL`, SHA-256 of the request, for telling a retry apart
+    /// from a different request reusing the key.
+    pub request_hash: Vec<u8>,
+    /// `SMALLINT NOT NULL`, HTTP status code of the first response.
+    pub response_status: i16,
+    /// `BYTEA NOT NULL`, body of the first response.
+    pub response_body: Vec<u8>,
+}
@@ -0,0 +1,49 @@
+//! Parsing of environment variables, with errors naming the variable.
+
+/// Value of the environment variable, or `None` if it's unset.
+pub fn parse<T>(name: &str) -> Result<Option<T>, String>
+where
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    match std::env::var(name) {
+        Ok(value) => match value.parse() {
+            Ok(n) => Ok(Some(n)),
+            Err(err) => Err(format!("Invalid {name} {value:?}: {err}")),
+        },
+        Err(std::env::VarError::NotPresent) => Ok(None),
+        Err(err) => Err(format!("Invalid {name}: {err}")),
+    }
+}
+
+/// Value of the environment variable, or the default if it's unset.
+pub fn parse_or<T>(name: &str, default: T) -> Result<T, String>
+where
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    Ok(parse(name)?.unwrap_or(default))
+}
+
+/// Like [`parse_or`], but rejects zero.
+pub fn positive_or<T>(name: &str, default: T) -> Result<T, String>
+where
+    T: std::str::FromStr + Default + PartialEq,
+    T::Err: std::fmt::Display,
+{
+    match parse(name)? {
+        Some(n) if n == T::default() => Err(format!("Invalid {name}: Must be positive")),
+        Some(n) => Ok(n),
+        None => Ok(default),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn defaults_unset_variable() {
+        assert_eq!(super::parse::<u64>("ENV_TEST_UNSET"), Ok(None));
+        assert_eq!(super::parse_or::<u64>("ENV_TEST_UNSET", 7), Ok(7));
+        assert_eq!(super::positive_or::<u64>("ENV_TEST_UNSET", 7), Ok(7));
+    }
+}
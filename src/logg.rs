@@ -1,32 +1,295 @@
-pub fn initialize_logger(level: log::LevelFilter) -> Result<log4rs::Handle, std::process::ExitCode> {
+pub enum Format {
+    /// Colored lines meant for humans.
+    Human,
+    /// One JSON object per line, meant for log aggregators. Structured
+    /// key-values of a record, e.g. `log::info!(book_id:% = id; "...")`, are
+    /// included in the object's field `attributes`, and the mapped diagnostic
+    /// context, e.g. the request ID, in the object's field `mdc`.
+    Json,
+}
+
+/// Log file that is rolled over once it reaches a maximum size, keeping a fixed
+/// number of rolled over files named `<path>.0`, `<path>.1` etc.
+pub struct RotatingFile {
+    pub path: std::path::PathBuf,
+    pub max_bytes: u64,
+    pub rotations: u32,
+}
+
+/// Target of the access log records written by the web server, one per HTTP
+/// request. They're routed to an appender of their own instead of those of the
+/// application's log records, so that they can be collected separately.
+pub const TARGET_ACCESS: &str = "access";
+
+/// Where and how log records are written. Records are always written to
+/// standard output, and additionally to a log file if one is configured.
+///
+/// Access log records are already formatted by the web server, and are written
+/// as such either to standard output or to a log file of their own.
+pub struct Output {
+    pub format: Format,
+    pub file: Option<RotatingFile>,
+    pub access_file: Option<RotatingFile>,
+}
+
+impl Output {
+    /// Reads the following environment variables:
+    ///
+    /// - `LOG_FORMAT`: `human` (default) or `json`.
+    /// - `LOG_FILE`: Path of a rotating log file. Unset by default.
+    /// - `LOG_FILE_MAX_BYTES`: Size at which the log file is rotated. Defaults
+    ///   to 10 MiB.
+    /// - `LOG_FILE_ROTATIONS`: Number of rotated log files kept. Defaults to 5.
+    /// - `ACCESS_LOG_FILE`: Path of a rotating access log file, rotated like
+    ///   the log file. Unset by default, i.e. access log records are written to
+    ///   standard output.
+    pub fn from_env() -> Result<Self, String> {
+        let format: Format = match std::env::var("LOG_FORMAT").as_deref() {
+            Err(std::env::VarError::NotPresent) | Ok("human") => Format::Human,
+            Ok("json") => Format::Json,
+            Ok(other) => return Err(format!("Invalid LOG_FORMAT {other:?}, expected \"human\" or \"json\"")),
+            Err(err) => return Err(format!("Invalid LOG_FORMAT: {err}")),
+        };
+
+        let file: Option<RotatingFile> = Self::rotating_file_from_env("LOG_FILE")?;
+        let access_file: Option<RotatingFile> = Self::rotating_file_from_env("ACCESS_LOG_FILE")?;
+
+        Ok(Self {
+            format,
+            file,
+            access_file,
+        })
+    }
+
+    fn rotating_file_from_env(name: &str) -> Result<Option<RotatingFile>, String> {
+        match std::env::var_os(name) {
+            None => Ok(None),
+            Some(path) => Ok(Some(RotatingFile {
+                path: path.into(),
+                max_bytes: crate::env::parse_or("LOG_FILE_MAX_BYTES", 10 * 1024 * 1024)?,
+                rotations: crate::env::parse_or("LOG_FILE_ROTATIONS", 5)?,
+            })),
+        }
+    }
+
+    fn encoder(&self) -> Box<dyn log4rs::encode::Encode> {
+        match self.format {
+            Format::Human => Box::new(log4rs::encode::pattern::PatternEncoder::new(
+                "{highlight({d(%Y-%m-%d %H:%M:%S)(utc)} UTC [{level}] {message})} {file}:{line} {X(request_id)}\n",
+            )),
+            Format::Json => Box::new(log4rs::encode::json::JsonEncoder::new()),
+        }
+    }
+}
+
+/// Log levels that can be changed at runtime via [`LevelControl`].
+#[derive(Clone)]
+pub struct Levels {
+    pub root: log::LevelFilter,
+    /// Levels of modules of this crate, e.g. `db` or `web::handlers`, that
+    /// differ from the root level.
+    pub modules: std::collections::BTreeMap<String, log::LevelFilter>,
+}
+
+/// Handle of the initialized logger, for changing log levels at runtime. Any
+/// change of levels replaces the whole logger config, so the appenders are
+/// rebuilt from the same [`Output`] each time.
+pub struct LevelControl {
+    handle: log4rs::Handle,
+    output: Output,
+    /// Current levels along with a counter of changes, so that a scheduled
+    /// revert can tell whether it has been superseded by a later change.
+    current: std::sync::Mutex<(Levels, u64)>,
+}
+
+impl LevelControl {
+    /// Applies the levels, returning the ones replaced and the number of the
+    /// change for [`LevelControl::revert`].
+    pub fn set(&self, levels: Levels) -> Result<(Levels, u64), ()> {
+        let mut current = match self.current.lock() {
+            Ok(n) => n,
+            Err(err) => {
+                log::error!("{err}");
+                return Err(());
+            }
+        };
+        let config: log4rs::Config = build_config(&self.output, &levels).map_err(|_code| ())?;
+        self.handle.set_config(config);
+
+        let (previous, change) = &*current;
+        let replaced: (Levels, u64) = (previous.clone(), change + 1);
+        *current = (levels, replaced.1);
+
+        Ok(replaced)
+    }
+
+    /// Restores the levels replaced by the given change, unless some other
+    /// change has been made since.
+    pub fn revert(&self, change: u64, previous: Levels) {
+        let mut current = match self.current.lock() {
+            Ok(n) => n,
+            Err(err) => {
+                log::error!("{err}");
+                return;
+            }
+        };
+        if current.1 != change {
+            log::info!("Not reverting log levels: Changed again since");
+            return;
+        }
+        match build_config(&self.output, &previous) {
+            Ok(config) => {
+                self.handle.set_config(config);
+                log::info!("Reverted log levels");
+                current.0 = previous;
+                current.1 += 1;
+            }
+            Err(_code) => {
+                log::error!("Reverting log levels failed");
+            }
+        }
+    }
+}
+
+pub fn initialize_logger(level: log::LevelFilter, output: Output) -> Result<LevelControl, std::process::ExitCode> {
+    let levels: Levels = Levels {
+        root: level,
+        modules: std::collections::BTreeMap::new(),
+    };
+    let config: log4rs::Config = build_config(&output, &levels)?;
+
+    match log4rs::init_config(config) {
+        Ok(handle) => Ok(LevelControl {
+            handle,
+            output,
+            current: std::sync::Mutex::new((levels, 0)),
+        }),
+        Err(err) => {
+            log::error!("Initializing logger failed: {err}");
+            Err(std::process::ExitCode::from(43))
+        }
+    }
+}
+
+fn build_config(output: &Output, levels: &Levels) -> Result<log4rs::Config, std::process::ExitCode> {
     const APPENDER_NAME_STDOUT: &str = "stdout";
+    const APPENDER_NAME_FILE: &str = "file";
+    const APPENDER_NAME_ACCESS: &str = "access";
 
     let appender_stdout = log4rs::append::console::ConsoleAppender::builder()
-        .encoder(Box::new(log4rs::encode::pattern::PatternEncoder::new(
-            "{highlight({d(%Y-%m-%d %H:%M:%S)(utc)} UTC [{level}] {message})} {file}:{line}\n",
-        )))
+        .encoder(output.encoder())
         .build();
 
     let appender_cfg_stdout =
         log4rs::config::Appender::builder().build(APPENDER_NAME_STDOUT, Box::new(appender_stdout));
 
-    let config = match log4rs::Config::builder().appender(appender_cfg_stdout).build(
-        log4rs::config::Root::builder()
-            .appender(APPENDER_NAME_STDOUT)
-            .build(level),
-    ) {
-        Ok(n) => n,
+    let mut config = log4rs::Config::builder().appender(appender_cfg_stdout);
+    let mut root = log4rs::config::Root::builder().appender(APPENDER_NAME_STDOUT);
+
+    if let Some(file) = &output.file {
+        let appender_file = build_rolling_file_appender(file, output.encoder())?;
+        config =
+            config.appender(log4rs::config::Appender::builder().build(APPENDER_NAME_FILE, Box::new(appender_file)));
+        root = root.appender(APPENDER_NAME_FILE);
+    }
+
+    let access_encoder: Box<dyn log4rs::encode::Encode> =
+        Box::new(log4rs::encode::pattern::PatternEncoder::new("{message}{n}"));
+    let appender_access: Box<dyn log4rs::append::Append> = match &output.access_file {
+        Some(file) => Box::new(build_rolling_file_appender(file, access_encoder)?),
+        None => Box::new(
+            log4rs::append::console::ConsoleAppender::builder()
+                .encoder(access_encoder)
+                .build(),
+        ),
+    };
+    config = config
+        .appender(log4rs::config::Appender::builder().build(APPENDER_NAME_ACCESS, appender_access))
+        .logger(
+            log4rs::config::Logger::builder()
+                .appender(APPENDER_NAME_ACCESS)
+                .additive(false)
+                .build(TARGET_ACCESS, log::LevelFilter::Info),
+        );
+
+    /*
+     * Records of modules with a level of their own are still written by the
+     * appenders of the root logger.
+     */
+    for (module, level) in &levels.modules {
+        let target: String = format!("{}::{module}", env!("CARGO_CRATE_NAME"));
+        config = config.logger(log4rs::config::Logger::builder().build(target, *level));
+    }
+
+    match config.build(root.build(levels.root)) {
+        Ok(n) => Ok(n),
         Err(err) => {
             log::error!("Building logger config failed: {err}");
-            return Err(std::process::ExitCode::from(42));
+            Err(std::process::ExitCode::from(42))
+        }
+    }
+}
+
+fn build_rolling_file_appender(
+    file: &RotatingFile,
+    encoder: Box<dyn log4rs::encode::Encode>,
+) -> Result<log4rs::append::rolling_file::RollingFileAppender, std::process::ExitCode> {
+    let roller_pattern: String = format!("{}.{{}}", file.path.display());
+    let roller = match log4rs::append::rolling_file::policy::compound::roll::fixed_window::FixedWindowRoller::builder()
+        .build(&roller_pattern, file.rotations)
+    {
+        Ok(n) => n,
+        Err(err) => {
+            log::error!("Building log file roller failed: {err}");
+            return Err(std::process::ExitCode::from(41));
         }
     };
+    let trigger = log4rs::append::rolling_file::policy::compound::trigger::size::SizeTrigger::new(file.max_bytes);
+    let policy =
+        log4rs::append::rolling_file::policy::compound::CompoundPolicy::new(Box::new(trigger), Box::new(roller));
 
-    match log4rs::init_config(config) {
-        Ok(handle) => Ok(handle),
+    match log4rs::append::rolling_file::RollingFileAppender::builder()
+        .encoder(encoder)
+        .build(&file.path, Box::new(policy))
+    {
+        Ok(n) => Ok(n),
         Err(err) => {
-            log::error!("Initializing logger failed: {err}");
-            Err(std::process::ExitCode::from(43))
+            log::error!("Opening log file {} failed: {err}", file.path.display());
+            Err(std::process::ExitCode::from(41))
         }
     }
 }
+
+/// Key of the mapped diagnostic context (MDC) entry that correlates log records
+/// with the HTTP request that caused them.
+pub const MDC_KEY_REQUEST_ID: &str = "request_id";
+
+/// Future that has an MDC entry set whenever it's being polled.
+///
+/// The MDC is thread-local, whereas many futures take turns on the same thread
+/// of an async runtime. Setting the entry only for the duration of each poll
+/// keeps the entries of concurrently handled requests from mixing up.
+pub struct WithMdc<F> {
+    inner: std::pin::Pin<Box<F>>,
+    key: &'static str,
+    value: String,
+}
+
+impl<F> WithMdc<F> {
+    pub fn new(inner: F, key: &'static str, value: String) -> Self {
+        Self {
+            inner: Box::pin(inner),
+            key,
+            value,
+        }
+    }
+}
+
+impl<F: Future> Future for WithMdc<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<Self::Output> {
+        let _guard = log_mdc::insert_scoped(self.key, self.value.clone());
+        self.inner.as_mut().poll(cx)
+    }
+}
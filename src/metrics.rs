@@ -0,0 +1,132 @@
+//! Prometheus metrics, collected in-process and exposed by the web server in
+//! the text exposition format.
+
+pub static METRICS: std::sync::LazyLock<Metrics> = std::sync::LazyLock::new(Metrics::register);
+
+/// Buckets in seconds, from sub-millisecond database round trips up to
+/// requests that are stuck behind a slow database.
+const DURATION_BUCKETS: &[f64] = &[
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+pub struct Metrics {
+    registry: prometheus::Registry,
+    started_at: std::time::Instant,
+
+    /// Labels: `method`, `route`, `status`.
+    pub http_requests: prometheus::IntCounterVec,
+    /// Labels: `method`, `route`, `status`.
+    pub http_request_duration: prometheus::HistogramVec,
+
+    /// Labels: `query`, i.e. the variant of [`crate::db::Query`].
+    pub db_query_duration: prometheus::HistogramVec,
+    pub db_queue_wait: prometheus::Histogram,
+    pub db_queue_depth: prometheus::IntGauge,
+    pub db_reconnects: prometheus::IntCounter,
+    pub db_shed_requests: prometheus::IntCounter,
+    /// Labels: `reason`, i.e. `abandoned` if the sender stopped waiting for
+    /// the response, or `expired` if the deadline of the request had passed.
+    pub db_cancelled_queries: prometheus::IntCounterVec,
+
+    process_uptime: prometheus::Gauge,
+}
+
+impl Metrics {
+    fn register() -> Self {
+        let registry: prometheus::Registry = prometheus::Registry::new();
+
+        let http_requests = prometheus::IntCounterVec::new(
+            prometheus::Opts::new("http_requests_total", "HTTP requests handled"),
+            &["method", "route", "status"],
+        )
+        .expect("metric descriptor is valid");
+        let http_request_duration = prometheus::HistogramVec::new(
+            prometheus::HistogramOpts::new("http_request_duration_seconds", "Latency of HTTP requests")
+                .buckets(DURATION_BUCKETS.to_vec()),
+            &["method", "route", "status"],
+        )
+        .expect("metric descriptor is valid");
+        let db_query_duration = prometheus::HistogramVec::new(
+            prometheus::HistogramOpts::new(
+                "db_query_duration_seconds",
+                "Time spent by the database actor handling a query",
+            )
+            .buckets(DURATION_BUCKETS.to_vec()),
+            &["query"],
+        )
+        .expect("metric descriptor is valid");
+        let db_queue_wait = prometheus::Histogram::with_opts(
+            prometheus::HistogramOpts::new(
+                "db_queue_wait_seconds",
+                "Time a query waited in the channel in front of the database actor before being handled",
+            )
+            .buckets(DURATION_BUCKETS.to_vec()),
+        )
+        .expect("metric descriptor is valid");
+        let db_queue_depth = prometheus::IntGauge::new(
+            "db_queue_depth",
+            "Queries waiting in the channel in front of the database actor",
+        )
+        .expect("metric descriptor is valid");
+        let db_reconnects = prometheus::IntCounter::new(
+            "db_reconnects_total",
+            "Connections established to the database after the initial one",
+        )
+        .expect("metric descriptor is valid");
+        let db_shed_requests = prometheus::IntCounter::new(
+            "db_shed_requests_total",
+            "Requests rejected because the channel in front of the database actor stayed full",
+        )
+        .expect("metric descriptor is valid");
+        let db_cancelled_queries = prometheus::IntCounterVec::new(
+            prometheus::Opts::new(
+                "db_cancelled_queries_total",
+                "Queries skipped by the database actor because nobody was waiting for the response anymore",
+            ),
+            &["reason"],
+        )
+        .expect("metric descriptor is valid");
+        let process_uptime = prometheus::Gauge::new("process_uptime_seconds", "Time since the process started")
+            .expect("metric descriptor is valid");
+
+        let collectors: [Box<dyn prometheus::core::Collector>; 9] = [
+            Box::new(http_requests.clone()),
+            Box::new(http_request_duration.clone()),
+            Box::new(db_query_duration.clone()),
+            Box::new(db_queue_wait.clone()),
+            Box::new(db_queue_depth.clone()),
+            Box::new(db_reconnects.clone()),
+            Box::new(db_shed_requests.clone()),
+            Box::new(db_cancelled_queries.clone()),
+            Box::new(process_uptime.clone()),
+        ];
+        for collector in collectors {
+            registry.register(collector).expect("metric names are unique");
+        }
+
+        Self {
+            registry,
+            started_at: std::time::Instant::now(),
+
+            http_requests,
+            http_request_duration,
+
+            db_query_duration,
+            db_queue_wait,
+            db_queue_depth,
+            db_reconnects,
+            db_shed_requests,
+            db_cancelled_queries,
+
+            process_uptime,
+        }
+    }
+
+    /// Metrics in the Prometheus text exposition format.
+    pub fn encode(&self) -> Result<String, prometheus::Error> {
+        self.process_uptime.set(self.started_at.elapsed().as_secs_f64());
+
+        let encoder: prometheus::TextEncoder = prometheus::TextEncoder::new();
+        encoder.encode_to_string(&self.registry.gather())
+    }
+}
@@ -0,0 +1,156 @@
+//! Configuration kept in files that can be changed while the program is
+//! running, e.g. for rotating keys without a restart.
+
+/// How often the modification times of watched files are checked.
+pub const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Value parsed from one or more files, parsed again when the modification time
+/// of any of them changes. The files are checked by a background thread every
+/// [`POLL_INTERVAL`], so that accessing the value never touches the file system.
+/// The thread stops once the value is dropped.
+///
+/// If a changed file can't be read or parsed, the error is logged and the
+/// previous value stays in effect, so that a half-written file doesn't take
+/// down whatever depends on it.
+pub struct Watched<T> {
+    current: std::sync::Arc<std::sync::RwLock<std::sync::Arc<T>>>,
+}
+
+/// Parses the contents of the watched files, in the order of their paths.
+type Parse<T> = dyn Fn(&[Vec<u8>]) -> Result<T, String> + Send + Sync;
+
+impl<T: Send + Sync + 'static> Watched<T> {
+    /// Fails if the file can't be read or parsed initially.
+    pub fn load(path: std::path::PathBuf, parse: fn(&[u8]) -> Result<T, String>) -> Result<Self, String> {
+        Self::load_all(vec![path], move |contents| parse(&contents[0]))
+    }
+
+    /// Like [`Self::load`], for values made up of several files, e.g. a
+    /// certificate and its private key. `parse` gets the contents of the files
+    /// in the order of `paths`.
+    pub fn load_all(
+        paths: Vec<std::path::PathBuf>,
+        parse: impl Fn(&[Vec<u8>]) -> Result<T, String> + Send + Sync + 'static,
+    ) -> Result<Self, String> {
+        let modified: Vec<Option<std::time::SystemTime>> = Self::modified(&paths);
+        let value: T = Self::read(&paths, &parse)?;
+        let current: std::sync::Arc<std::sync::RwLock<std::sync::Arc<T>>> =
+            std::sync::Arc::new(std::sync::RwLock::new(std::sync::Arc::new(value)));
+
+        let watched: std::sync::Weak<std::sync::RwLock<std::sync::Arc<T>>> = std::sync::Arc::downgrade(&current);
+        let display: String = Self::display(&paths);
+        if let Err(err) = std::thread::Builder::new()
+            .name("reload".to_owned())
+            .spawn(move || Self::poll(paths, &parse, modified, watched))
+        {
+            return Err(format!("Watching {display} failed: {err}"));
+        }
+
+        Ok(Self { current })
+    }
+
+    pub fn get(&self) -> std::sync::Arc<T> {
+        match self.current.read() {
+            Ok(n) => n.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        }
+    }
+
+    /// Reloads the value whenever the files change, until it's dropped.
+    fn poll(
+        paths: Vec<std::path::PathBuf>,
+        parse: &Parse<T>,
+        mut modified: Vec<Option<std::time::SystemTime>>,
+        watched: std::sync::Weak<std::sync::RwLock<std::sync::Arc<T>>>,
+    ) {
+        loop {
+            std::thread::sleep(POLL_INTERVAL);
+            let Some(current) = watched.upgrade() else {
+                return;
+            };
+
+            let checked: Vec<Option<std::time::SystemTime>> = Self::modified(&paths);
+            if checked == modified {
+                continue;
+            }
+            modified = checked;
+
+            match Self::read(&paths, parse) {
+                Ok(value) => {
+                    log::info!("Reloaded {}", Self::display(&paths));
+                    match current.write() {
+                        Ok(mut n) => *n = std::sync::Arc::new(value),
+                        Err(poisoned) => *poisoned.into_inner() = std::sync::Arc::new(value),
+                    }
+                }
+                Err(err) => {
+                    log::error!("Keeping previous contents of {}: {err}", Self::display(&paths));
+                }
+            }
+        }
+    }
+
+    fn display(paths: &[std::path::PathBuf]) -> String {
+        let paths: Vec<String> = paths.iter().map(|path| path.display().to_string()).collect();
+        paths.join(", ")
+    }
+
+    fn modified(paths: &[std::path::PathBuf]) -> Vec<Option<std::time::SystemTime>> {
+        paths
+            .iter()
+            .map(|path| std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok())
+            .collect()
+    }
+
+    fn read(paths: &[std::path::PathBuf], parse: &Parse<T>) -> Result<T, String> {
+        let mut contents: Vec<Vec<u8>> = Vec::new();
+        for path in paths {
+            match std::fs::read(path) {
+                Ok(n) => contents.push(n),
+                Err(err) => return Err(format!("Reading {} failed: {err}", path.display())),
+            }
+        }
+        parse(&contents).map_err(|err| format!("Parsing {} failed: {err}", Self::display(paths)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    fn parse(contents: &[u8]) -> Result<String, String> {
+        match std::str::from_utf8(contents) {
+            Ok("") => Err("Empty".to_owned()),
+            Ok(n) => Ok(n.to_owned()),
+            Err(err) => Err(err.to_string()),
+        }
+    }
+
+    /// Waits up to a few poll intervals for the value to become `expected`.
+    fn wait_for(watched: &super::Watched<String>, expected: &str) {
+        let deadline: std::time::Instant = std::time::Instant::now() + 3 * super::POLL_INTERVAL;
+        while watched.get().as_str() != expected {
+            assert!(std::time::Instant::now() < deadline, "Still {:?}", watched.get());
+            std::thread::sleep(std::time::Duration::from_millis(50));
+        }
+    }
+
+    #[test]
+    fn reloads_changed_file_in_background() {
+        let dir: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let path: std::path::PathBuf = dir.path().join("value");
+        std::fs::write(&path, "first").unwrap();
+        let watched: super::Watched<String> = super::Watched::load(path.clone(), parse).unwrap();
+        assert_eq!(watched.get().as_str(), "first");
+
+        std::fs::write(&path, "second").unwrap();
+        wait_for(&watched, "second");
+
+        /*
+         * An invalid file is skipped, a valid one after it is picked up again.
+         */
+        std::fs::write(&path, "").unwrap();
+        std::thread::sleep(2 * super::POLL_INTERVAL);
+        assert_eq!(watched.get().as_str(), "second");
+        std::fs::write(&path, "third").unwrap();
+        wait_for(&watched, "third");
+    }
+}
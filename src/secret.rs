@@ -0,0 +1,127 @@
+//! Credentials, kept out of logs and error messages.
+
+/// Shown in place of a secret value.
+pub const REDACTED: &str = "<redacted>";
+
+/// Secrets shorter than this are rejected, as they'd be likely to occur in
+/// text by chance, e.g. within a word, which scrubbing them would mangle.
+const MIN_CHARS: usize = 8;
+
+/// Value that is never printed through [`std::fmt::Debug`] or
+/// [`std::fmt::Display`], so that it can't end up in a log record by accident.
+/// The value itself is only available via [`Secret::expose`].
+#[derive(Clone)]
+pub struct Secret(String);
+
+impl Secret {
+    /// Reads the secret from the first of the following that is set:
+    ///
+    /// 1. File at the path in environment variable `file_var`.
+    /// 2. Environment variable `var`.
+    /// 3. File at `default_file`, if it exists, e.g. as mounted by Docker or
+    ///    systemd's `LoadCredential=`.
+    ///
+    /// Trailing line breaks are stripped from files. `None` if none is set.
+    /// Secrets shorter than [`MIN_CHARS`] are rejected.
+    pub fn from_env_or_file(var: &str, file_var: &str, default_file: &str) -> Result<Option<Self>, String> {
+        if let Some(path) = std::env::var_os(file_var) {
+            return match Self::read(std::path::Path::new(&path)) {
+                Ok(n) => Ok(Some(n)),
+                Err(err) => Err(format!("Invalid {file_var}: {err}")),
+            };
+        }
+        match std::env::var(var) {
+            Ok(value) => {
+                return match Self::new(value) {
+                    Ok(n) => Ok(Some(n)),
+                    Err(err) => Err(format!("Invalid {var}: {err}")),
+                };
+            }
+            Err(std::env::VarError::NotPresent) => {}
+            Err(err) => return Err(format!("Invalid {var}: {err}")),
+        }
+        let default_file: &std::path::Path = std::path::Path::new(default_file);
+        if default_file.exists() {
+            return Self::read(default_file).map(Some);
+        }
+
+        Ok(None)
+    }
+
+    fn read(path: &std::path::Path) -> Result<Self, String> {
+        match std::fs::read_to_string(path) {
+            Ok(n) => Self::new(n.trim_end_matches(['\r', '\n']).to_owned())
+                .map_err(|err| format!("Invalid {}: {err}", path.display())),
+            Err(err) => Err(format!("Reading {} failed: {err}", path.display())),
+        }
+    }
+
+    fn new(value: String) -> Result<Self, String> {
+        if value.chars().count() < MIN_CHARS {
+            return Err(format!("Must be at least {MIN_CHARS} characters"));
+        }
+        Ok(Self(value))
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    /// Replaces occurrences of the secret, and of its `encodings`, e.g. the
+    /// percent-encoded form, in text that may have picked it up. Only a
+    /// fallback for text that can't be kept free of the secret in the first
+    /// place, e.g. error messages of libraries.
+    pub fn scrub(&self, text: &str, encodings: &[&str]) -> String {
+        let mut scrubbed: String = text.replace(&self.0, REDACTED);
+        for encoding in encodings {
+            scrubbed = scrubbed.replace(encoding, REDACTED);
+        }
+        scrubbed
+    }
+}
+
+impl std::fmt::Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Secret({REDACTED})")
+    }
+}
+
+impl std::fmt::Display for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{REDACTED}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn scrubs_long_secret_and_its_encodings() {
+        let secret: super::Secret = super::Secret("p@ss word".to_owned());
+        let text: &str = "Failed with p@ss word, or p%40ss%20word";
+
+        assert_eq!(
+            secret.scrub(text, &["p%40ss%20word"]),
+            "Failed with <redacted>, or <redacted>"
+        );
+    }
+
+    #[test]
+    fn rejects_short_secret() {
+        let dir: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let path: std::path::PathBuf = dir.path().join("secret");
+        let path_str: &str = path.to_str().unwrap();
+
+        std::fs::write(&path, "1234567\n").unwrap();
+        let err: String = match super::Secret::from_env_or_file("SECRET_TEST_UNSET", "SECRET_TEST_FILE_UNSET", path_str)
+        {
+            Ok(_secret) => panic!("Short secret was accepted"),
+            Err(err) => err,
+        };
+        assert_eq!(err, format!("Invalid {path_str}: Must be at least 8 characters"));
+
+        std::fs::write(&path, "12345678\n").unwrap();
+        let secret: Option<super::Secret> =
+            super::Secret::from_env_or_file("SECRET_TEST_UNSET", "SECRET_TEST_FILE_UNSET", path_str).unwrap();
+        assert_eq!(secret.unwrap().expose(), "12345678");
+    }
+}
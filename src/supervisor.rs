@@ -0,0 +1,398 @@
+//! Supervision of the essential actors: The supervisor owns the database actor
+//! and the web server actor, watches the thread and the task they run on, and
+//! restarts them according to a [`RestartPolicy`] if they crash. When restarts
+//! are exhausted, the failure is escalated to the terminator.
+
+pub struct Actor {
+    term: crate::term::Handle,
+    systemd: crate::systemd::Handle,
+    restarts: Restarts,
+
+    db_config: crate::db::Config,
+    db_mailbox: crate::db::Mailbox,
+    /// The database actor is not stopped by the global shutdown signal
+    /// directly, but only once the web server has drained its traffic.
+    db_term: crate::term::Handle,
+    db_stop: tokio_util::sync::CancellationToken,
+    /// First incarnation of the database actor, connected eagerly in
+    /// [`Actor::init`] so that an unreachable database is noticed at startup.
+    db_initial: Option<crate::db::Actor>,
+
+    web_config: crate::web::Config,
+    log_levels: std::sync::Arc<crate::logg::LevelControl>,
+}
+
+/// Restarts are one-for-one: Only the crashed child is restarted, while the
+/// others keep running undisturbed.
+pub struct RestartPolicy {
+    /// Maximum number of restarts allowed within `window` before the failure
+    /// is escalated to the terminator.
+    pub max_restarts: usize,
+    pub window: std::time::Duration,
+    /// Delay before the first restart within `window`, doubled for each
+    /// consecutive restart, up to `backoff_max`.
+    pub backoff_initial: std::time::Duration,
+    pub backoff_max: std::time::Duration,
+}
+
+impl RestartPolicy {
+    fn backoff(&self, consecutive_restarts: usize) -> std::time::Duration {
+        let exponent: u32 = consecutive_restarts.saturating_sub(1).min(16) as u32;
+        self.backoff_initial.saturating_mul(1 << exponent).min(self.backoff_max)
+    }
+}
+
+/// Restarts within the window of the policy, oldest first.
+struct Restarts {
+    policy: RestartPolicy,
+    within_window: std::collections::VecDeque<std::time::Instant>,
+}
+
+impl Restarts {
+    fn new(policy: RestartPolicy) -> Self {
+        Self {
+            policy,
+            within_window: std::collections::VecDeque::new(),
+        }
+    }
+
+    /// Records a crash at `now`, returning how long to wait before restarting
+    /// the crashed child, or `None` if restarts are exhausted, i.e. if the
+    /// failure is to be escalated.
+    fn record(&mut self, now: std::time::Instant) -> Option<std::time::Duration> {
+        self.within_window
+            .retain(|restarted_at| now.duration_since(*restarted_at) < self.policy.window);
+        if self.within_window.len() >= self.policy.max_restarts {
+            return None;
+        }
+        self.within_window.push_back(now);
+
+        Some(self.policy.backoff(self.within_window.len()))
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Child {
+    Db,
+    Web,
+}
+
+struct DbChild {
+    thread: std::thread::JoinHandle<()>,
+    /// Resolves with an error if the thread exits without reporting a summary,
+    /// i.e. if it panics or fails to set up its runtime.
+    exited: tokio::sync::oneshot::Receiver<crate::db::Summary>,
+}
+
+struct WebChild {
+    task: tokio::task::JoinHandle<crate::web::Summary>,
+}
+
+impl Actor {
+    pub fn init(
+        term: crate::term::Handle,
+        systemd: crate::systemd::Handle,
+        policy: RestartPolicy,
+        db_mailbox: crate::db::Mailbox,
+        db_config: crate::db::Config,
+        web_config: crate::web::Config,
+        log_levels: std::sync::Arc<crate::logg::LevelControl>,
+    ) -> Result<Self, crate::db::ConnectError> {
+        let (db_term, db_stop) = term.detached();
+
+        let db_actor: crate::db::Actor = crate::db::Actor::connect(db_term.clone(), &db_config, db_mailbox.clone())?;
+
+        Ok(Self {
+            term,
+            systemd,
+            restarts: Restarts::new(policy),
+
+            db_config,
+            db_mailbox,
+            db_term,
+            db_stop,
+            db_initial: Some(db_actor),
+
+            web_config,
+            log_levels,
+        })
+    }
+
+    pub async fn work(mut self) -> Summary {
+        let token: tokio_util::sync::CancellationToken = self.term.clone().token();
+
+        let mut db: DbChild = self.spawn_db();
+        let mut web: WebChild = self.spawn_web();
+        let mut db_alive: bool = true;
+        let mut web_alive: bool = true;
+
+        let mut summary: Summary = Summary::Completed;
+
+        loop {
+            let crashed: Child = tokio::select! {
+                biased;
+                _ = token.cancelled() => {
+                    break;
+                }
+                exited = &mut db.exited => {
+                    db_alive = false;
+                    /*
+                     * Withholds watchdog pings until the restarted actor is
+                     * handling queries again, see [`crate::systemd`].
+                     */
+                    self.db_mailbox.get_progress().mark_down();
+                    /*
+                     * The database actor only exits with a summary once stopped
+                     * by the supervisor after the supervision loop, so exiting
+                     * here in any way is a failure.
+                     */
+                    match exited {
+                        Ok(_) => log::error!("Database actor exited unexpectedly"),
+                        Err(_) => log::error!("Database actor crashed"),
+                    }
+                    Child::Db
+                }
+                exited = &mut web.task => {
+                    web_alive = false;
+                    match exited {
+                        Ok(_) => {
+                            /*
+                             * The web server only exits on its own accord after
+                             * having triggered termination itself, e.g. when it
+                             * fails to bind its listener.
+                             */
+                            token.cancelled().await;
+                            break;
+                        }
+                        Err(err) => {
+                            log::error!("Web server actor crashed: {err}");
+                            Child::Web
+                        }
+                    }
+                }
+            };
+
+            let Some(backoff) = self.restarts.record(std::time::Instant::now()) else {
+                log::error!(
+                    "{crashed:?} actor crashed more than {} times within {:?}, escalating",
+                    self.restarts.policy.max_restarts,
+                    self.restarts.policy.window
+                );
+                summary = Summary::Escalated;
+                self.term
+                    .trigger_termination(crate::term::TriggerGlobalCancellation::Supervisor)
+                    .await;
+                token.cancelled().await;
+                break;
+            };
+
+            log::warn!("Restarting {crashed:?} actor in {backoff:?}");
+            if token.run_until_cancelled(tokio::time::sleep(backoff)).await.is_none() {
+                break;
+            }
+
+            match crashed {
+                Child::Db => {
+                    db = self.spawn_db();
+                    db_alive = true;
+                }
+                Child::Web => {
+                    web = self.spawn_web();
+                    web_alive = true;
+                }
+            }
+        }
+
+        /*
+         * The web server observes the global cancellation on its own, so here
+         * we only wait for it to finish draining its traffic. Only then is the
+         * database actor, which serves that traffic, stopped.
+         */
+        if web_alive && let Err(err) = web.task.await {
+            log::error!("Web server actor crashed during shutdown: {err}");
+        }
+        self.db_stop.cancel();
+        if db_alive && let Err(err) = db.exited.await {
+            log::error!("Database actor crashed during shutdown: {err}");
+        }
+        if let Err(err) = db.thread.join() {
+            log::error!("{err:?}");
+        }
+
+        summary
+    }
+
+    /// Dedicated OS thread for blocking workloads.
+    fn spawn_db(&mut self) -> DbChild {
+        let (tx_exited, rx_exited) = tokio::sync::oneshot::channel::<crate::db::Summary>();
+
+        let db_term: crate::term::Handle = self.db_term.clone();
+        let initial: Option<crate::db::Actor> = self.db_initial.take();
+        let config: crate::db::Config = self.db_config.clone();
+        let mailbox: crate::db::Mailbox = self.db_mailbox.clone();
+
+        let thread = std::thread::spawn(move || {
+            let db_actor: crate::db::Actor = match initial {
+                Some(n) => n,
+                None => match crate::db::Actor::connect(db_term, &config, mailbox) {
+                    Ok(n) => {
+                        crate::metrics::METRICS.db_reconnects.inc();
+                        n
+                    }
+                    Err(err) => {
+                        log::error!("{err}");
+                        return;
+                    }
+                },
+            };
+            let runtime: tokio::runtime::Runtime = match tokio::runtime::Builder::new_current_thread()
+                .enable_io()
+                .enable_time()
+                .build()
+            {
+                Ok(n) => n,
+                Err(err) => {
+                    log::error!("{err}");
+                    return;
+                }
+            };
+            let done: crate::db::Summary = runtime.block_on(db_actor.work());
+            let _ = tx_exited.send(done);
+        });
+
+        DbChild {
+            thread,
+            exited: rx_exited,
+        }
+    }
+
+    fn spawn_web(&self) -> WebChild {
+        let web_server: crate::web::Actor = crate::web::Actor::init(
+            self.term.clone(),
+            self.systemd.clone(),
+            &self.web_config,
+            self.db_mailbox.get_handle(),
+            self.log_levels.clone(),
+        );
+
+        WebChild {
+            task: tokio::task::spawn(web_server.work()),
+        }
+    }
+}
+
+pub enum Summary {
+    Completed,
+    /// Some actor crashed more often than the restart policy allows.
+    Escalated,
+}
+
+impl Summary {
+    pub fn exit_code(&self) -> std::process::ExitCode {
+        match self {
+            Summary::Completed => std::process::ExitCode::SUCCESS,
+            Summary::Escalated => std::process::ExitCode::from(46),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    fn policy() -> super::RestartPolicy {
+        super::RestartPolicy {
+            max_restarts: 3,
+            window: std::time::Duration::from_secs(10),
+            backoff_initial: std::time::Duration::from_millis(20),
+            backoff_max: std::time::Duration::from_millis(50),
+        }
+    }
+
+    #[test]
+    fn doubles_backoff_up_to_max() {
+        let mut restarts: super::Restarts = super::Restarts::new(policy());
+        let now: std::time::Instant = std::time::Instant::now();
+
+        assert_eq!(restarts.record(now), Some(std::time::Duration::from_millis(20)));
+        assert_eq!(restarts.record(now), Some(std::time::Duration::from_millis(40)));
+        assert_eq!(restarts.record(now), Some(std::time::Duration::from_millis(50)));
+        assert_eq!(restarts.record(now), None);
+    }
+
+    #[test]
+    fn forgets_restarts_outside_of_window() {
+        let mut restarts: super::Restarts = super::Restarts::new(policy());
+        let start: std::time::Instant = std::time::Instant::now();
+
+        assert!(restarts.record(start).is_some());
+        assert!(restarts.record(start + std::time::Duration::from_secs(1)).is_some());
+        assert!(restarts.record(start + std::time::Duration::from_secs(2)).is_some());
+        /*
+         * The first restart has left the window, so there's room for one more,
+         * with the backoff of the third consecutive restart.
+         */
+        assert_eq!(
+            restarts.record(start + std::time::Duration::from_secs(10)),
+            Some(std::time::Duration::from_millis(50))
+        );
+        assert_eq!(restarts.record(start + std::time::Duration::from_secs(10)), None);
+        /*
+         * Long after, counting starts over.
+         */
+        assert_eq!(
+            restarts.record(start + std::time::Duration::from_secs(60)),
+            Some(std::time::Duration::from_millis(20))
+        );
+    }
+
+    /// The database actor can't connect to a port nobody listens on, so it
+    /// crashes each time it's started.
+    #[tokio::test]
+    async fn escalates_child_that_keeps_crashing() {
+        let terminator: crate::term::Actor = crate::term::Actor::hook();
+        let db_mailbox: crate::db::Mailbox = crate::db::Mailbox::open(1);
+        let systemd_notifier: crate::systemd::Actor =
+            crate::systemd::Actor::hook(terminator.get_handle(), db_mailbox.get_progress());
+        let term: crate::term::Handle = terminator.get_handle();
+        let (db_term, db_stop) = term.detached();
+        let supervisor: super::Actor = super::Actor {
+            term,
+            systemd: systemd_notifier.get_handle(),
+            restarts: super::Restarts::new(policy()),
+
+            db_config: crate::db::Config {
+                connection_string: "postgres://postgres@127.0.0.1:1/postgres?connect_timeout=1".to_owned(),
+                password: None,
+                tls: crate::db::tls::Tls::from_env().unwrap(),
+                query_log: crate::db::query_log::Policy {
+                    mode: crate::db::query_log::Mode::Placeholders,
+                    slow_threshold: None,
+                },
+            },
+            db_mailbox,
+            db_term,
+            db_stop,
+            db_initial: None,
+
+            web_config: crate::web::testing::config(),
+            log_levels: crate::web::testing::log_levels(),
+        };
+
+        let started_at: std::time::Instant = std::time::Instant::now();
+        let triggered = async {
+            let triggerer: Option<crate::term::TriggerGlobalCancellation> = terminator.work_until_triggered().await;
+            (triggerer, started_at.elapsed())
+        };
+        let (supervised, (triggerer, escalated_after)) = tokio::join!(supervisor.work(), triggered);
+
+        assert_eq!(triggerer, Some(crate::term::TriggerGlobalCancellation::Supervisor));
+        assert!(matches!(supervised, super::Summary::Escalated));
+        assert_eq!(supervised.exit_code(), std::process::ExitCode::from(46));
+        /*
+         * Three restarts, waiting 20, 40 and 50 milliseconds before each.
+         */
+        assert!(
+            escalated_after >= std::time::Duration::from_millis(110),
+            "{escalated_after:?}"
+        );
+    }
+}
@@ -0,0 +1,423 @@
+//! Integration with systemd's service manager notification protocol, for
+//! running as a service with `Type=notify` (and optionally `WatchdogSec=`).
+//! See `man 3 sd_notify`.
+
+/// How long each internal liveness check may take before the checked actor is
+/// considered unresponsive.
+const LIVENESS_CHECK_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// How long the database actor may be busy with a single query before it's
+/// considered stuck. Queries made on behalf of requests are cut short by their
+/// deadline well before this.
+const DB_STALL_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Header with which the liveness check identifies itself to the web server,
+/// so that it's left out of the access log and the metrics. See
+/// [`is_liveness_check`].
+const LIVENESS_CHECK_HEADER: &str = "x-liveness-check";
+
+/// Value of [`LIVENESS_CHECK_HEADER`], random per process so that clients
+/// can't hide their requests from the access log by sending the header.
+static LIVENESS_CHECK_TOKEN: std::sync::LazyLock<String> =
+    std::sync::LazyLock::new(|| uuid::Uuid::new_v4().simple().to_string());
+
+/// Whether the request was sent by the liveness check of the web server.
+pub fn is_liveness_check(headers: &axum::http::HeaderMap) -> bool {
+    headers
+        .get(LIVENESS_CHECK_HEADER)
+        .is_some_and(|value| value.as_bytes() == LIVENESS_CHECK_TOKEN.as_bytes())
+}
+
+pub struct Actor {
+    term: crate::term::Handle,
+
+    /// `None` if not running under systemd, i.e. if `NOTIFY_SOCKET` is unset.
+    notify_socket: Option<(std::os::unix::net::UnixDatagram, std::os::unix::net::SocketAddr)>,
+    /// `None` if systemd doesn't expect watchdog pings.
+    watchdog_interval: Option<std::time::Duration>,
+
+    db_progress: crate::db::Progress,
+    chan_listening: (
+        tokio::sync::mpsc::Sender<Listening>,
+        tokio::sync::mpsc::Receiver<Listening>,
+    ),
+}
+
+/// Where the web server listens, for checking its liveness.
+struct Listening {
+    address: std::net::SocketAddr,
+    tls: bool,
+}
+
+impl Actor {
+    /// Reads the environment variables set by systemd. Misconfiguration is
+    /// logged and the notifications are then simply not sent.
+    pub fn hook(term: crate::term::Handle, db_progress: crate::db::Progress) -> Self {
+        let notify_socket = match std::env::var_os("NOTIFY_SOCKET") {
+            Some(path) => match Self::open_notify_socket(&path) {
+                Ok(n) => Some(n),
+                Err(err) => {
+                    log::error!("Cannot notify systemd via {path:?}: {err}");
+                    None
+                }
+            },
+            None => None,
+        };
+
+        Self::new(term, notify_socket, Self::watchdog_interval(), db_progress)
+    }
+
+    fn new(
+        term: crate::term::Handle,
+        notify_socket: Option<(std::os::unix::net::UnixDatagram, std::os::unix::net::SocketAddr)>,
+        watchdog_interval: Option<std::time::Duration>,
+        db_progress: crate::db::Progress,
+    ) -> Self {
+        Self {
+            term,
+
+            notify_socket,
+            watchdog_interval,
+
+            db_progress,
+            chan_listening: tokio::sync::mpsc::channel::<Listening>(1),
+        }
+    }
+
+    pub fn get_handle(&self) -> Handle {
+        Handle {
+            write: self.chan_listening.0.clone(),
+        }
+    }
+
+    pub async fn work(mut self) -> Summary {
+        let Some((socket, address)) = self.notify_socket.take() else {
+            return Summary;
+        };
+        let notify = |state: &str| {
+            if let Err(err) = socket.send_to_addr(state.as_bytes(), &address) {
+                log::error!("Failed to notify systemd of {state:?}: {err}");
+            }
+        };
+        let token: tokio_util::sync::CancellationToken = self.term.token();
+
+        /*
+         * The database connection is set up before any actor starts working, so
+         * here it's enough to wait for the web server to bind its listener.
+         */
+        let mut web_listening: Listening = tokio::select! {
+            _ = token.cancelled() => {
+                notify("STOPPING=1");
+                return Summary;
+            }
+            received = self.chan_listening.1.recv() => match received {
+                Some(n) => n,
+                None => {
+                    log::error!("Listening notification channel closed without signal");
+                    return Summary;
+                }
+            }
+        };
+        notify("READY=1");
+
+        let mut watchdog: Option<tokio::time::Interval> = self.watchdog_interval.map(tokio::time::interval);
+        loop {
+            tokio::select! {
+                _ = token.cancelled() => {
+                    notify("STOPPING=1");
+                    break;
+                }
+                received = self.chan_listening.1.recv() => {
+                    /*
+                     * The web server was restarted.
+                     */
+                    if let Some(n) = received {
+                        web_listening = n;
+                    }
+                }
+                _ = Self::tick(&mut watchdog) => {
+                    if Self::check_db_alive(&self.db_progress) && Self::check_web_alive(&web_listening).await {
+                        notify("WATCHDOG=1");
+                    }
+                }
+            }
+        }
+
+        Summary
+    }
+
+    fn open_notify_socket(
+        path: &std::ffi::OsStr,
+    ) -> std::io::Result<(std::os::unix::net::UnixDatagram, std::os::unix::net::SocketAddr)> {
+        use std::os::unix::ffi::OsStrExt;
+
+        let address: std::os::unix::net::SocketAddr = match path.as_bytes() {
+            [b'@', abstract_name @ ..] => {
+                use std::os::linux::net::SocketAddrExt;
+                std::os::unix::net::SocketAddr::from_abstract_name(abstract_name)?
+            }
+            _ => std::os::unix::net::SocketAddr::from_pathname(path)?,
+        };
+        let socket: std::os::unix::net::UnixDatagram = std::os::unix::net::UnixDatagram::unbound()?;
+
+        Ok((socket, address))
+    }
+
+    /// Half of `WATCHDOG_USEC`, as recommended by `man 3 sd_watchdog_enabled`.
+    fn watchdog_interval() -> Option<std::time::Duration> {
+        if let Ok(pid) = std::env::var("WATCHDOG_PID")
+            && pid != std::process::id().to_string()
+        {
+            return None;
+        }
+
+        let usec: u64 = match std::env::var("WATCHDOG_USEC").ok()?.parse() {
+            Ok(n) => n,
+            Err(err) => {
+                log::error!("Invalid WATCHDOG_USEC: {err}");
+                return None;
+            }
+        };
+
+        Some(std::time::Duration::from_micros(usec / 2))
+    }
+
+    async fn tick(watchdog: &mut Option<tokio::time::Interval>) {
+        match watchdog {
+            Some(n) => {
+                n.tick().await;
+            }
+            None => std::future::pending().await,
+        }
+    }
+
+    /// Checks that the database actor isn't stuck handling a query. Being busy
+    /// with a long query, or having many queued, doesn't count as stuck.
+    fn check_db_alive(db_progress: &crate::db::Progress) -> bool {
+        if db_progress.is_down() {
+            log::warn!("Database actor is down");
+            return false;
+        }
+        match db_progress.busy_for() {
+            Some(n) if n > DB_STALL_TIMEOUT => {
+                log::warn!("Database actor has been handling the same query for {n:?}");
+                false
+            }
+            _ => true,
+        }
+    }
+
+    /// First flight of a TLS handshake. The server certificate is never
+    /// verified, as the handshake is abandoned right after the server answers.
+    fn client_hello() -> Result<Vec<u8>, rustls::Error> {
+        let provider: std::sync::Arc<rustls::crypto::CryptoProvider> =
+            std::sync::Arc::new(rustls::crypto::ring::default_provider());
+        let client_config: rustls::ClientConfig = rustls::ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()?
+            .with_root_certificates(rustls::RootCertStore::empty())
+            .with_no_client_auth();
+        let mut connection: rustls::ClientConnection = rustls::ClientConnection::new(
+            std::sync::Arc::new(client_config),
+            rustls::pki_types::ServerName::from(std::net::IpAddr::from(std::net::Ipv4Addr::LOCALHOST)),
+        )?;
+
+        let mut client_hello: Vec<u8> = Vec::new();
+        if let Err(err) = connection.write_tls(&mut client_hello) {
+            return Err(rustls::Error::General(err.to_string()));
+        }
+
+        Ok(client_hello)
+    }
+
+    /// Requests the liveness probe of the web server over the loopback, i.e.
+    /// checks that the web server is accepting and serving connections.
+    ///
+    /// With HTTPS, only checks that connections are accepted and that the TLS
+    /// handshake is started, as no client certificate could be presented if
+    /// mutual TLS is required.
+    async fn check_web_alive(web_listening: &Listening) -> bool {
+        use tokio::io::AsyncReadExt;
+        use tokio::io::AsyncWriteExt;
+
+        let web_address: std::net::SocketAddr = web_listening.address;
+        let tls: bool = web_listening.tls;
+        let round_trip = async {
+            let mut stream: tokio::net::TcpStream = tokio::net::TcpStream::connect(web_address).await?;
+            if tls {
+                let client_hello: Vec<u8> = Self::client_hello().map_err(std::io::Error::other)?;
+                stream.write_all(&client_hello).await?;
+                let mut record_type: [u8; 1] = [0];
+                stream.read_exact(&mut record_type).await?;
+                /*
+                 * Handshake record, i.e. ServerHello, or an alert if the
+                 * server didn't like the hello. Either way, it's serving.
+                 */
+                return Ok::<bool, std::io::Error>(record_type[0] == 0x16 || record_type[0] == 0x15);
+            }
+            let request: String = format!(
+                "GET /healthz HTTP/1.1\r\nHost: localhost\r\n{LIVENESS_CHECK_HEADER}: {}\r\nConnection: close\r\n\r\n",
+                *LIVENESS_CHECK_TOKEN
+            );
+            stream.write_all(request.as_bytes()).await?;
+            let mut response: Vec<u8> = Vec::new();
+            stream.read_to_end(&mut response).await?;
+            Ok::<bool, std::io::Error>(response.starts_with(b"HTTP/1.1 2"))
+        };
+
+        match tokio::time::timeout(LIVENESS_CHECK_TIMEOUT, round_trip).await {
+            Ok(Ok(alive)) => alive,
+            Ok(Err(err)) => {
+                log::warn!("Web server actor failed liveness check: {err}");
+                false
+            }
+            Err(_elapsed) => {
+                log::warn!("Web server actor didn't answer liveness check within {LIVENESS_CHECK_TIMEOUT:?}");
+                false
+            }
+        }
+    }
+}
+
+pub struct Summary;
+
+#[derive(Clone)]
+pub struct Handle {
+    write: tokio::sync::mpsc::Sender<Listening>,
+}
+
+impl Handle {
+    /// To be called by the web server once it has bound its listener.
+    pub async fn notify_listening(&self, address: std::net::SocketAddr, tls: bool) {
+        /*
+         * Channel is closed if not running under systemd, in which case there's
+         * no one to notify.
+         */
+        let _ = self.write.send(Listening { address, tls }).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    /// Answers each request with 200 OK, like the liveness probe of the web
+    /// server, after checking that the liveness check identified itself.
+    async fn serve_healthz(listener: tokio::net::TcpListener) {
+        use tokio::io::AsyncReadExt;
+        use tokio::io::AsyncWriteExt;
+
+        loop {
+            let (mut stream, _peer) = listener.accept().await.unwrap();
+            let mut request: Vec<u8> = Vec::new();
+            while !request.ends_with(b"\r\n\r\n") {
+                let mut byte: [u8; 1] = [0];
+                stream.read_exact(&mut byte).await.unwrap();
+                request.push(byte[0]);
+            }
+            let request: String = String::from_utf8(request).unwrap();
+            let header: String = format!("{}: {}\r\n", super::LIVENESS_CHECK_HEADER, *super::LIVENESS_CHECK_TOKEN);
+            assert!(request.starts_with("GET /healthz "), "{request}");
+            assert!(request.contains(&header), "{request}");
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+                .await
+                .unwrap();
+        }
+    }
+
+    /// Socket of systemd bound in the directory, and the notification socket
+    /// connecting to it.
+    fn notify_socket(
+        dir: &tempfile::TempDir,
+    ) -> (
+        tokio::net::UnixDatagram,
+        (std::os::unix::net::UnixDatagram, std::os::unix::net::SocketAddr),
+    ) {
+        let path: std::path::PathBuf = dir.path().join("notify.sock");
+        let systemd: tokio::net::UnixDatagram = tokio::net::UnixDatagram::bind(&path).unwrap();
+        let notify_socket = (
+            std::os::unix::net::UnixDatagram::unbound().unwrap(),
+            std::os::unix::net::SocketAddr::from_pathname(&path).unwrap(),
+        );
+        (systemd, notify_socket)
+    }
+
+    #[tokio::test]
+    async fn notifies_ready_then_watchdog_then_stopping() {
+        let dir: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let (systemd, notify_socket) = notify_socket(&dir);
+
+        let terminator: crate::term::Actor = crate::term::Actor::hook();
+        let (term, stop) = terminator.get_handle().detached();
+        let actor: super::Actor = super::Actor::new(
+            term,
+            Some(notify_socket),
+            Some(std::time::Duration::from_millis(10)),
+            crate::db::Progress::default(),
+        );
+        let handle: super::Handle = actor.get_handle();
+        let work: tokio::task::JoinHandle<super::Summary> = tokio::spawn(actor.work());
+
+        let listener: tokio::net::TcpListener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let web_address: std::net::SocketAddr = listener.local_addr().unwrap();
+        tokio::spawn(serve_healthz(listener));
+        handle.notify_listening(web_address, false).await;
+
+        let mut states: Vec<String> = Vec::new();
+        let mut buf: [u8; 64] = [0; 64];
+        while states.iter().filter(|state| *state == "WATCHDOG=1").count() < 2 {
+            let n: usize = systemd.recv(&mut buf).await.unwrap();
+            states.push(String::from_utf8(buf[..n].to_vec()).unwrap());
+        }
+        stop.cancel();
+        work.await.unwrap();
+        while let Ok(n) = systemd.try_recv(&mut buf) {
+            states.push(String::from_utf8(buf[..n].to_vec()).unwrap());
+        }
+
+        assert_eq!(states.first().map(String::as_str), Some("READY=1"), "{states:?}");
+        assert_eq!(states.last().map(String::as_str), Some("STOPPING=1"), "{states:?}");
+        assert!(
+            states[1..states.len() - 1].iter().all(|state| state == "WATCHDOG=1"),
+            "{states:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn withholds_watchdog_while_db_actor_is_down() {
+        let dir: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let (systemd, notify_socket) = notify_socket(&dir);
+
+        let db_progress: crate::db::Progress = crate::db::Progress::default();
+        db_progress.mark_down();
+        let terminator: crate::term::Actor = crate::term::Actor::hook();
+        let (term, stop) = terminator.get_handle().detached();
+        let actor: super::Actor = super::Actor::new(
+            term,
+            Some(notify_socket),
+            Some(std::time::Duration::from_millis(10)),
+            db_progress.clone(),
+        );
+        let handle: super::Handle = actor.get_handle();
+        let work: tokio::task::JoinHandle<super::Summary> = tokio::spawn(actor.work());
+
+        let listener: tokio::net::TcpListener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let web_address: std::net::SocketAddr = listener.local_addr().unwrap();
+        tokio::spawn(serve_healthz(listener));
+        handle.notify_listening(web_address, false).await;
+
+        let mut buf: [u8; 64] = [0; 64];
+        let n: usize = systemd.recv(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"READY=1");
+        /*
+         * Pings would be due every 10 milliseconds.
+         */
+        let withheld = tokio::time::timeout(std::time::Duration::from_millis(200), systemd.recv(&mut buf)).await;
+        assert!(withheld.is_err(), "{:?}", withheld.map(|n| buf[..n.unwrap()].to_vec()));
+
+        db_progress.mark_up();
+        let n: usize = systemd.recv(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"WATCHDOG=1");
+
+        stop.cancel();
+        work.await.unwrap();
+    }
+}
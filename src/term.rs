@@ -46,10 +46,20 @@ impl Actor {
 
         Summary
     }
+
+    /// Like [`Actor::work`], but only for cancellation triggered by another
+    /// actor, returning which one.
+    #[cfg(test)]
+    pub async fn work_until_triggered(mut self) -> Option<TriggerGlobalCancellation> {
+        let received: Option<TriggerGlobalCancellation> = self.chan_trigger.1.recv().await;
+        self.global_cancellation_token.cancel();
+        received
+    }
 }
 
 pub struct Summary;
 
+#[derive(Clone)]
 pub struct Handle {
     read: tokio_util::sync::CancellationToken,
     write: tokio::sync::mpsc::Sender<TriggerGlobalCancellation>,
@@ -62,11 +72,26 @@ impl Handle {
         }
     }
 
+    /// Handle whose token is not cancelled along with the global shutdown
+    /// signal, but only via the returned token. Useful for actors that have to
+    /// keep working until other actors have finished. Termination can still be
+    /// triggered via the returned handle.
+    pub fn detached(&self) -> (Handle, tokio_util::sync::CancellationToken) {
+        let read: tokio_util::sync::CancellationToken = tokio_util::sync::CancellationToken::new();
+        let handle: Handle = Handle {
+            read: read.clone(),
+            write: self.write.clone(),
+        };
+        (handle, read)
+    }
+
     pub fn token(self) -> tokio_util::sync::CancellationToken {
         self.read
     }
 }
 
+#[derive(Debug, PartialEq)]
 pub enum TriggerGlobalCancellation {
     WebServer,
+    Supervisor,
 }
@@ -0,0 +1,147 @@
+//! Span-based instrumentation, exported using OpenTelemetry. Spans are created
+//! with the macros of crate _tracing_, e.g. `tracing::info_span!`, and are only
+//! recorded if some exporter is configured.
+
+const SERVICE_NAME: &str = "poc-http-json-crud-postgres";
+
+/// Where spans are exported to. Any combination of the exporters may be used.
+pub struct Exporters {
+    /// OTLP over HTTP, e.g. `http://127.0.0.1:4318/v1/traces`.
+    pub otlp_endpoint: Option<String>,
+    /// File to which spans are appended as JSON lines.
+    pub file: Option<std::path::PathBuf>,
+}
+
+impl Exporters {
+    /// Reads the following environment variables, both unset by default:
+    ///
+    /// - `TRACE_OTLP_ENDPOINT`: OTLP over HTTP endpoint to export spans to.
+    /// - `TRACE_FILE`: Path of a file to export spans to.
+    pub fn from_env() -> Self {
+        Self {
+            otlp_endpoint: std::env::var("TRACE_OTLP_ENDPOINT").ok(),
+            file: std::env::var_os("TRACE_FILE").map(std::path::PathBuf::from),
+        }
+    }
+}
+
+/// Returns `None` if no exporter is configured. Otherwise the returned provider
+/// has to be shut down before the program exits, in order to flush the spans
+/// that haven't been exported yet.
+pub fn initialize_tracer(
+    exporters: Exporters,
+) -> Result<Option<opentelemetry_sdk::trace::SdkTracerProvider>, std::process::ExitCode> {
+    use opentelemetry::trace::TracerProvider;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    if exporters.otlp_endpoint.is_none() && exporters.file.is_none() {
+        return Ok(None);
+    }
+
+    let mut provider = opentelemetry_sdk::trace::SdkTracerProvider::builder().with_resource(
+        opentelemetry_sdk::Resource::builder()
+            .with_service_name(SERVICE_NAME)
+            .build(),
+    );
+
+    if let Some(endpoint) = exporters.otlp_endpoint {
+        use opentelemetry_otlp::WithExportConfig;
+        let exporter = match opentelemetry_otlp::SpanExporter::builder()
+            .with_http()
+            .with_endpoint(&endpoint)
+            .build()
+        {
+            Ok(n) => n,
+            Err(err) => {
+                log::error!("Building OTLP span exporter for {endpoint} failed: {err}");
+                return Err(std::process::ExitCode::from(47));
+            }
+        };
+        provider = provider.with_batch_exporter(exporter);
+    }
+
+    if let Some(path) = exporters.file {
+        let exporter = match JsonLinesExporter::open(&path) {
+            Ok(n) => n,
+            Err(err) => {
+                log::error!("Opening span export file {} failed: {err}", path.display());
+                return Err(std::process::ExitCode::from(47));
+            }
+        };
+        provider = provider.with_batch_exporter(exporter);
+    }
+
+    let provider: opentelemetry_sdk::trace::SdkTracerProvider = provider.build();
+    let subscriber =
+        tracing_subscriber::registry().with(tracing_opentelemetry::layer().with_tracer(provider.tracer(SERVICE_NAME)));
+    if let Err(err) = tracing::subscriber::set_global_default(subscriber) {
+        log::error!("Initializing tracer failed: {err}");
+        return Err(std::process::ExitCode::from(47));
+    }
+
+    /*
+     * For continuing traces from header `traceparent` of inbound requests.
+     */
+    opentelemetry::global::set_text_map_propagator(opentelemetry_sdk::propagation::TraceContextPropagator::new());
+
+    Ok(Some(provider))
+}
+
+/// Writes each span as a JSON object on its own line.
+#[derive(Debug)]
+struct JsonLinesExporter {
+    file: std::sync::Mutex<std::io::LineWriter<std::fs::File>>,
+}
+
+impl JsonLinesExporter {
+    fn open(path: &std::path::Path) -> std::io::Result<Self> {
+        let file: std::fs::File = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+
+        Ok(Self {
+            file: std::sync::Mutex::new(std::io::LineWriter::new(file)),
+        })
+    }
+
+    fn unix_nanos(instant: std::time::SystemTime) -> u128 {
+        instant
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|n| n.as_nanos())
+            .unwrap_or_default()
+    }
+}
+
+impl opentelemetry_sdk::trace::SpanExporter for JsonLinesExporter {
+    async fn export(&self, batch: Vec<opentelemetry_sdk::trace::SpanData>) -> opentelemetry_sdk::error::OTelSdkResult {
+        use std::io::Write;
+
+        let mut file = match self.file.lock() {
+            Ok(n) => n,
+            Err(err) => {
+                return Err(opentelemetry_sdk::error::OTelSdkError::InternalFailure(err.to_string()));
+            }
+        };
+
+        for span in batch {
+            let attributes: serde_json::Map<String, serde_json::Value> = span
+                .attributes
+                .iter()
+                .map(|kv| (kv.key.to_string(), serde_json::Value::String(kv.value.to_string())))
+                .collect();
+            let line = serde_json::json!({
+                "trace_id": span.span_context.trace_id().to_string(),
+                "span_id": span.span_context.span_id().to_string(),
+                "parent_span_id": span.parent_span_id.to_string(),
+                "name": span.name,
+                "start_time_unix_nano": Self::unix_nanos(span.start_time).to_string(),
+                "end_time_unix_nano": Self::unix_nanos(span.end_time).to_string(),
+                "attributes": attributes,
+            });
+
+            if let Err(err) = writeln!(file, "{line}") {
+                return Err(opentelemetry_sdk::error::OTelSdkError::InternalFailure(err.to_string()));
+            }
+        }
+
+        Ok(())
+    }
+}
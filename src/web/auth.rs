@@ -0,0 +1,357 @@
+//! Authentication of clients and authorization of what they may do. Clients are
+//! authenticated by [`crate::web::middleware::authenticate`], and handlers get
+//! hold of the result via the [`Principal`] extractor.
+
+/// Prefix of the API keys issued, telling them apart from other bearer tokens.
+const API_KEY_PREFIX: &str = "poc_";
+
+/// Number of random bytes in an API key, encoded as hex after the prefix.
+const API_KEY_RANDOM_BYTES: usize = 32;
+
+/// Longest [`Principal::owner`] accepted, as it's stored in columns of
+/// `VARCHAR(256)`.
+pub const OWNER_MAX_CHARS: usize = 256;
+
+/// Longest name of an API key, so that it fits in [`Principal::owner`].
+pub const API_KEY_NAME_MAX_CHARS: usize = OWNER_MAX_CHARS - "key:".len();
+
+/// Permission on the books API, named `books:read` etc.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Scope {
+    Read,
+    Write,
+    /// Implies all the other scopes, and additionally grants access to the
+    /// admin endpoints, e.g. for managing API keys.
+    Admin,
+}
+
+impl std::fmt::Display for Scope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Scope::Read => write!(f, "books:read"),
+            Scope::Write => write!(f, "books:write"),
+            Scope::Admin => write!(f, "books:admin"),
+        }
+    }
+}
+
+impl std::str::FromStr for Scope {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "books:read" => Ok(Scope::Read),
+            "books:write" => Ok(Scope::Write),
+            "books:admin" => Ok(Scope::Admin),
+            other => Err(format!(
+                "Unknown scope {other:?}, expected \"books:read\", \"books:write\" or \"books:admin\""
+            )),
+        }
+    }
+}
+
+/// Authenticated client. As an extractor, rejects requests that were not
+/// authenticated with 401 Unauthorized.
+#[derive(Clone)]
+pub struct Principal {
+    /// E.g. ID of the API key.
+    pub id: String,
+    pub name: String,
+    /// Identity under which books are owned, namespaced by how the principal
+    /// authenticated so that identities of different kinds can't collide:
+    ///
+    /// - `key:<name>` for API keys, so that a key rotated by revoking it and
+    ///   issuing a new one with the same name keeps owning its books.
+    /// - `jwt:<issuer>|<subject>` for JWTs.
+    /// - `admin:token` for the admin token.
+    pub owner: String,
+    pub scopes: Vec<Scope>,
+}
+
+impl Principal {
+    /// Principal of the admin token configured at startup, e.g. for issuing
+    /// the first API keys.
+    pub fn admin_token() -> Self {
+        Self {
+            id: "admin-token".to_owned(),
+            name: "Admin token".to_owned(),
+            owner: "admin:token".to_owned(),
+            scopes: vec![Scope::Admin],
+        }
+    }
+
+    /// Scopes that are not known to this version of the program are ignored.
+    pub fn from_api_key(api_key: &crate::db::schema_v1::ApiKey) -> Self {
+        let scopes: Vec<Scope> = api_key
+            .scopes
+            .iter()
+            .filter_map(|scope| match scope.parse() {
+                Ok(n) => Some(n),
+                Err(err) => {
+                    log::warn!("Ignoring scope of API key {}: {err}", api_key.id);
+                    None
+                }
+            })
+            .collect();
+
+        Self {
+            id: api_key.id.to_string(),
+            name: api_key.name.clone(),
+            owner: format!("key:{}", api_key.name),
+            scopes,
+        }
+    }
+
+    /// Authority for changing books on behalf of the principal.
+    pub fn authority(&self) -> crate::db::Authority {
+        if self.scopes.contains(&Scope::Admin) {
+            crate::db::Authority::Admin
+        } else {
+            crate::db::Authority::Owner(self.owner.clone())
+        }
+    }
+
+    /// Returns 403 Forbidden if the principal lacks the scope.
+    pub fn authorize(&self, scope: Scope) -> Result<(), axum::http::StatusCode> {
+        if self.scopes.contains(&scope) || self.scopes.contains(&Scope::Admin) {
+            return Ok(());
+        }
+        log::warn!(principal = self.id.as_str(); "Forbidden: {} lacks scope {scope}", self.name);
+        Err(axum::http::StatusCode::FORBIDDEN)
+    }
+}
+
+impl<S: Send + Sync> axum::extract::FromRequestParts<S> for Principal {
+    type Rejection = axum::response::Response;
+
+    async fn from_request_parts(parts: &mut axum::http::request::Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        match parts.extensions.get::<Principal>() {
+            Some(principal) => Ok(principal.clone()),
+            None => {
+                log::warn!("Unauthorized: No credentials");
+                Err(unauthorized())
+            }
+        }
+    }
+}
+
+/// 401 Unauthorized, with the challenge telling clients how to authenticate.
+pub fn unauthorized() -> axum::response::Response {
+    use axum::response::IntoResponse;
+
+    (
+        axum::http::StatusCode::UNAUTHORIZED,
+        [(axum::http::header::WWW_AUTHENTICATE, "Bearer")],
+    )
+        .into_response()
+}
+
+/// Whether the bearer token looks like an API key, as opposed to some other
+/// kind of token.
+pub fn is_api_key(token: &str) -> bool {
+    token.starts_with(API_KEY_PREFIX)
+}
+
+pub fn generate_api_key() -> Result<String, getrandom::Error> {
+    let mut random: [u8; API_KEY_RANDOM_BYTES] = [0; API_KEY_RANDOM_BYTES];
+    getrandom::fill(&mut random)?;
+
+    let mut api_key: String = String::from(API_KEY_PREFIX);
+    for byte in random {
+        api_key.push_str(&format!("{byte:02x}"));
+    }
+
+    Ok(api_key)
+}
+
+/// API keys are long random strings, so a fast hash suffices to keep them
+/// from being recovered from the database.
+pub fn hash_api_key(api_key: &str) -> Vec<u8> {
+    use sha2::Digest;
+
+    sha2::Sha256::digest(api_key.as_bytes()).to_vec()
+}
+
+/// Compares secrets without short-circuiting on the first differing byte, so
+/// that response times don't reveal how much of a guess was right.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    #[tokio::test]
+    async fn rejects_requests_without_valid_credentials() {
+        let db_mailbox: crate::db::Mailbox = crate::db::Mailbox::open(1);
+        let address: std::net::SocketAddr =
+            crate::web::testing::serve(&crate::web::testing::config(), &db_mailbox).await;
+
+        for headers in [
+            &[][..],
+            &["authorization: Basic YWRtaW46YWRtaW4="][..],
+            &["authorization: Bearer not-a-known-token"][..],
+        ] {
+            let response: crate::web::testing::Response =
+                crate::web::testing::send(address, "GET", "/api/books/v1", headers, "").await;
+            assert_eq!(response.status, 401, "{headers:?}");
+            assert_eq!(response.header("www-authenticate"), Some("Bearer"), "{headers:?}");
+        }
+    }
+
+    #[test]
+    fn authorizes_by_scope() {
+        let reader: super::Principal = super::Principal {
+            id: "reader".to_owned(),
+            name: "Reader".to_owned(),
+            owner: "key:Reader".to_owned(),
+            scopes: vec![super::Scope::Read],
+        };
+        assert_eq!(reader.authorize(super::Scope::Read), Ok(()));
+        assert_eq!(
+            reader.authorize(super::Scope::Write),
+            Err(axum::http::StatusCode::FORBIDDEN)
+        );
+        assert_eq!(
+            reader.authorize(super::Scope::Admin),
+            Err(axum::http::StatusCode::FORBIDDEN)
+        );
+
+        let admin: super::Principal = super::Principal::admin_token();
+        assert_eq!(admin.authorize(super::Scope::Write), Ok(()));
+    }
+
+    /// Requires PostgreSQL, see [`crate::web::testing::connect_db`].
+    #[tokio::test]
+    #[ignore]
+    async fn forbids_read_scope_to_write() {
+        let db_mailbox: crate::db::Mailbox = crate::web::testing::connect_db();
+        let address: std::net::SocketAddr =
+            crate::web::testing::serve(&crate::web::testing::config(), &db_mailbox).await;
+        let name: String = format!("reader-{}", uuid::Uuid::new_v4());
+        let (_id, key) = crate::web::testing::issue_api_key(address, &name, &["books:read"]).await;
+        let authorization: String = format!("authorization: Bearer {key}");
+
+        let response: crate::web::testing::Response =
+            crate::web::testing::send(address, "GET", "/api/books/v1", &[&authorization], "").await;
+        assert_eq!(response.status, 200);
+
+        let response: crate::web::testing::Response = crate::web::testing::send(
+            address,
+            "POST",
+            "/api/books/v1/genre/history",
+            &[&authorization, "content-type: application/json"],
+            r#"{"title":"Title","page_count":1}"#,
+        )
+        .await;
+        assert_eq!(response.status, 403);
+    }
+
+    /// Requires PostgreSQL, see [`crate::web::testing::connect_db`].
+    #[tokio::test]
+    #[ignore]
+    async fn rejects_revoked_api_key() {
+        let db_mailbox: crate::db::Mailbox = crate::web::testing::connect_db();
+        let address: std::net::SocketAddr =
+            crate::web::testing::serve(&crate::web::testing::config(), &db_mailbox).await;
+        let name: String = format!("revoked-{}", uuid::Uuid::new_v4());
+        let (id, key) = crate::web::testing::issue_api_key(address, &name, &["books:read"]).await;
+        let authorization: String = format!("authorization: Bearer {key}");
+
+        let response: crate::web::testing::Response =
+            crate::web::testing::send(address, "GET", "/api/books/v1", &[&authorization], "").await;
+        assert_eq!(response.status, 200);
+
+        let response: crate::web::testing::Response = crate::web::testing::send(
+            address,
+            "DELETE",
+            &format!("/admin/api-keys/{id}"),
+            &[&format!("authorization: Bearer {}", crate::web::testing::ADMIN_TOKEN)],
+            "",
+        )
+        .await;
+        assert_eq!(response.status, 204);
+
+        let response: crate::web::testing::Response =
+            crate::web::testing::send(address, "GET", "/api/books/v1", &[&authorization], "").await;
+        assert_eq!(response.status, 401);
+    }
+
+    /// Requires PostgreSQL, see [`crate::web::testing::connect_db`].
+    #[tokio::test]
+    #[ignore]
+    async fn reuses_name_of_revoked_api_key_only() {
+        let db_mailbox: crate::db::Mailbox = crate::web::testing::connect_db();
+        let address: std::net::SocketAddr =
+            crate::web::testing::serve(&crate::web::testing::config(), &db_mailbox).await;
+        let name: String = format!("rotated-{}", uuid::Uuid::new_v4());
+        let admin_authorization: String = format!("authorization: Bearer {}", crate::web::testing::ADMIN_TOKEN);
+        let (id, _key) = crate::web::testing::issue_api_key(address, &name, &["books:read"]).await;
+
+        let body: String = serde_json::json!({ "name": name, "scopes": ["books:read"] }).to_string();
+        let response: crate::web::testing::Response = crate::web::testing::send(
+            address,
+            "POST",
+            "/admin/api-keys",
+            &[&admin_authorization, "content-type: application/json"],
+            &body,
+        )
+        .await;
+        assert_eq!(response.status, 409);
+
+        let response: crate::web::testing::Response = crate::web::testing::send(
+            address,
+            "DELETE",
+            &format!("/admin/api-keys/{id}"),
+            &[&admin_authorization],
+            "",
+        )
+        .await;
+        assert_eq!(response.status, 204);
+
+        let (rotated_id, _key) = crate::web::testing::issue_api_key(address, &name, &["books:read"]).await;
+        assert_ne!(rotated_id, id);
+    }
+
+    /// Requires PostgreSQL, see [`crate::web::testing::connect_db`].
+    #[tokio::test]
+    #[ignore]
+    async fn stores_only_hash_of_api_key() {
+        use diesel::Connection;
+        use diesel::ExpressionMethods;
+        use diesel::RunQueryDsl;
+        use diesel::SelectableHelper;
+        use diesel::query_dsl::methods::FilterDsl;
+        use diesel::query_dsl::methods::SelectDsl;
+
+        let db_mailbox: crate::db::Mailbox = crate::web::testing::connect_db();
+        let address: std::net::SocketAddr =
+            crate::web::testing::serve(&crate::web::testing::config(), &db_mailbox).await;
+        let name: String = format!("hashed-{}", uuid::Uuid::new_v4());
+        let (id, key) = crate::web::testing::issue_api_key(address, &name, &["books:read"]).await;
+
+        let mut db_connection: diesel::PgConnection =
+            diesel::PgConnection::establish(&std::env::var("DB_TEST_URI").unwrap()).unwrap();
+        let stored: crate::db::schema_v1::ApiKey = crate::db::schema_v1::api_keys::table
+            .filter(crate::db::schema_v1::api_keys::id.eq(uuid::Uuid::parse_str(&id).unwrap()))
+            .select(crate::db::schema_v1::ApiKey::as_select())
+            .get_result(&mut db_connection)
+            .unwrap();
+        assert_eq!(stored.key_hash, super::hash_api_key(&key));
+        assert_ne!(stored.key_hash, key.as_bytes());
+        assert!(!stored.name.contains(&key) && !stored.scopes.contains(&key));
+
+        let response: crate::web::testing::Response = crate::web::testing::send(
+            address,
+            "GET",
+            "/admin/api-keys",
+            &[&format!("authorization: Bearer {}", crate::web::testing::ADMIN_TOKEN)],
+            "",
+        )
+        .await;
+        assert_eq!(response.status, 200);
+        assert!(response.body.contains(&id) && !response.body.contains(&key));
+    }
+}
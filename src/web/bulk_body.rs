@@ -0,0 +1,325 @@
+//! Splitting of bulk request bodies into one frame per operation as the body
+//! arrives, so that operations can be applied before the whole body has been
+//! received, see [`crate::web::handlers::books_v1::post_bulk`].
+
+/// Limit of the size of a single operation. Books are far smaller.
+pub const ITEM_MAX_BYTES: usize = 64 * 1024;
+
+/// A frame, or why it can't be parsed. Frames are not parsed as operations
+/// yet, so the error of a single frame only fails that operation.
+pub type Item = Result<tokio_util::bytes::Bytes, String>;
+
+pub enum Decoder {
+    /// `application/x-ndjson`: One operation per line. Blank lines are
+    /// skipped.
+    Ndjson(tokio_util::codec::LinesCodec),
+    /// `application/json`: Array of operations.
+    JsonArray(JsonArrayDecoder),
+}
+
+impl Decoder {
+    pub fn ndjson() -> Self {
+        Self::Ndjson(tokio_util::codec::LinesCodec::new_with_max_length(ITEM_MAX_BYTES))
+    }
+
+    pub fn json_array() -> Self {
+        Self::JsonArray(JsonArrayDecoder::new(ITEM_MAX_BYTES))
+    }
+
+    /// Takes the next frame off the buffer, `None` if it needs more data.
+    /// Fails if the body is malformed as a whole, e.g. not an array.
+    pub fn decode(&mut self, buf: &mut tokio_util::bytes::BytesMut) -> Result<Option<Item>, String> {
+        self.decode_with(buf, false)
+    }
+
+    /// Like [`Decoder::decode`], once the body has ended. Fails if the body
+    /// was cut short, e.g. an array without its closing bracket.
+    pub fn decode_eof(&mut self, buf: &mut tokio_util::bytes::BytesMut) -> Result<Option<Item>, String> {
+        self.decode_with(buf, true)
+    }
+
+    fn decode_with(&mut self, buf: &mut tokio_util::bytes::BytesMut, eof: bool) -> Result<Option<Item>, String> {
+        use tokio_util::codec::Decoder;
+
+        match self {
+            Self::Ndjson(codec) => loop {
+                let line: Result<Option<String>, tokio_util::codec::LinesCodecError> = match eof {
+                    true => codec.decode_eof(buf),
+                    false => codec.decode(buf),
+                };
+                match line {
+                    Ok(Some(line)) if line.trim().is_empty() => {}
+                    Ok(Some(line)) => return Ok(Some(Ok(line.into()))),
+                    Ok(None) => return Ok(None),
+                    /*
+                     * The codec skips the rest of the line, so the following
+                     * lines are unaffected.
+                     */
+                    Err(tokio_util::codec::LinesCodecError::MaxLineLengthExceeded) => {
+                        return Ok(Some(Err(format!("Line exceeds {ITEM_MAX_BYTES} bytes"))));
+                    }
+                    Err(tokio_util::codec::LinesCodecError::Io(err)) => {
+                        return Ok(Some(Err(format!("Line is not UTF-8: {err}"))));
+                    }
+                }
+            },
+            Self::JsonArray(decoder) => match decoder.decode(buf)? {
+                Some(item) => Ok(Some(item)),
+                None if eof && decoder.state != ArrayState::AfterArray => {
+                    Err("Expected a JSON array of operations: Unexpected end of the array".to_owned())
+                }
+                None => Ok(None),
+            },
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum ArrayState {
+    BeforeArray,
+    /// `first` until the first element, which may be the closing bracket.
+    BeforeElement {
+        first: bool,
+    },
+    InElement,
+    AfterElement,
+    AfterArray,
+}
+
+/// Splits a JSON array into its elements without parsing them, by tracking
+/// nesting and strings. An element ends with a comma or the closing bracket of
+/// the array outside of any nested value.
+pub struct JsonArrayDecoder {
+    state: ArrayState,
+    max_length: usize,
+    /// Bytes of the current element that have been scanned already.
+    scanned: usize,
+    depth: usize,
+    in_string: bool,
+    escaped: bool,
+    /// Whether the current element has exceeded `max_length`, and is being
+    /// dropped as it's scanned.
+    discarding: bool,
+}
+
+impl JsonArrayDecoder {
+    pub fn new(max_length: usize) -> Self {
+        Self {
+            state: ArrayState::BeforeArray,
+            max_length,
+            scanned: 0,
+            depth: 0,
+            in_string: false,
+            escaped: false,
+            discarding: false,
+        }
+    }
+
+    fn decode(&mut self, buf: &mut tokio_util::bytes::BytesMut) -> Result<Option<Item>, String> {
+        use tokio_util::bytes::Buf;
+
+        loop {
+            if self.state != ArrayState::InElement {
+                let whitespace: usize = buf.iter().take_while(|byte| byte.is_ascii_whitespace()).count();
+                buf.advance(whitespace);
+            }
+            let Some(&next) = buf.first() else {
+                return Ok(None);
+            };
+
+            match self.state {
+                ArrayState::BeforeArray => {
+                    if next != b'[' {
+                        return Err("Expected a JSON array of operations".to_owned());
+                    }
+                    buf.advance(1);
+                    self.state = ArrayState::BeforeElement { first: true };
+                }
+                ArrayState::BeforeElement { first } => match next {
+                    b']' if first => {
+                        buf.advance(1);
+                        self.state = ArrayState::AfterArray;
+                    }
+                    b']' | b',' => {
+                        return Err(format!(
+                            "Expected a JSON array of operations: Expected an operation, found {:?}",
+                            next as char
+                        ));
+                    }
+                    _ => {
+                        self.state = ArrayState::InElement;
+                    }
+                },
+                ArrayState::InElement => return Ok(self.decode_element(buf)),
+                ArrayState::AfterElement => {
+                    buf.advance(1);
+                    self.state = match next {
+                        b',' => ArrayState::BeforeElement { first: false },
+                        _ => ArrayState::AfterArray,
+                    };
+                }
+                ArrayState::AfterArray => {
+                    return Err("Expected a JSON array of operations: Unexpected data after the array".to_owned());
+                }
+            }
+        }
+    }
+
+    fn decode_element(&mut self, buf: &mut tokio_util::bytes::BytesMut) -> Option<Item> {
+        let mut end: Option<usize> = None;
+        for (i, byte) in buf.iter().enumerate().skip(self.scanned) {
+            if self.in_string {
+                match byte {
+                    _ if self.escaped => self.escaped = false,
+                    b'\\' => self.escaped = true,
+                    b'"' => self.in_string = false,
+                    _ => {}
+                }
+                continue;
+            }
+            match byte {
+                b'"' => self.in_string = true,
+                b'{' | b'[' => self.depth += 1,
+                b']' if self.depth == 0 => {
+                    end = Some(i);
+                    break;
+                }
+                b'}' | b']' => self.depth = self.depth.saturating_sub(1),
+                b',' if self.depth == 0 => {
+                    end = Some(i);
+                    break;
+                }
+                _ => {}
+            }
+        }
+
+        let Some(end) = end else {
+            if self.discarding || buf.len() > self.max_length {
+                self.discarding = true;
+                buf.clear();
+                self.scanned = 0;
+            } else {
+                self.scanned = buf.len();
+            }
+            return None;
+        };
+
+        /*
+         * The comma or bracket that ended the element is left for
+         * `ArrayState::AfterElement`.
+         */
+        let element: tokio_util::bytes::BytesMut = buf.split_to(end);
+        let discarded: bool = self.discarding || element.len() > self.max_length;
+        self.state = ArrayState::AfterElement;
+        self.scanned = 0;
+        self.depth = 0;
+        self.discarding = false;
+        if discarded {
+            return Some(Err(format!("Operation exceeds {} bytes", self.max_length)));
+        }
+        Some(Ok(element.freeze()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    /// Feeds the body in chunks of the given size, and collects the frames.
+    fn decode(mut decoder: super::Decoder, body: &str, chunk_size: usize) -> Result<Vec<super::Item>, String> {
+        let mut buf: tokio_util::bytes::BytesMut = tokio_util::bytes::BytesMut::new();
+        let mut items: Vec<super::Item> = Vec::new();
+        for chunk in body.as_bytes().chunks(chunk_size) {
+            buf.extend_from_slice(chunk);
+            while let Some(item) = decoder.decode(&mut buf)? {
+                items.push(item);
+            }
+        }
+        while let Some(item) = decoder.decode_eof(&mut buf)? {
+            items.push(item);
+        }
+        Ok(items)
+    }
+
+    fn frames(items: Vec<super::Item>) -> Vec<Result<String, String>> {
+        items
+            .into_iter()
+            .map(|item| item.map(|frame| String::from_utf8(frame.to_vec()).unwrap().trim().to_owned()))
+            .collect()
+    }
+
+    const CREATE: &str = r#"{"op":"create","genre":"history","title":"a, [b]","page_count":1}"#;
+    const PUT: &str =
+        r#"{"op":"put","id":"0b6e2a5e-7e43-4ad2-a5b4-0d3e41fa5e84","genre":"horror","title":"\"}], {","page_count":2}"#;
+    const DELETE: &str = r#"{"op":"delete","id":"0b6e2a5e-7e43-4ad2-a5b4-0d3e41fa5e84"}"#;
+
+    #[test]
+    fn splits_json_array_across_chunks() {
+        /*
+         * Unknown fields are only rejected once the operation is parsed, so
+         * nested values must be framed too.
+         */
+        let nested: &str = r#"{"op":"delete","id":"0b6e2a5e-7e43-4ad2-a5b4-0d3e41fa5e84","x":[1,{"y":[]}]}"#;
+        let body: String = format!(" [ {CREATE} , {PUT},\n            {nested} ] ");
+
+        for chunk_size in [1, 2, 7, body.len()] {
+            let items: Vec<super::Item> = decode(super::Decoder::json_array(), &body, chunk_size).unwrap();
+            assert_eq!(
+                frames(items),
+                vec![Ok(CREATE.to_owned()), Ok(PUT.to_owned()), Ok(nested.to_owned())],
+                "{chunk_size}"
+            );
+        }
+        assert!(decode(super::Decoder::json_array(), "[]", 1).unwrap().is_empty());
+    }
+
+    #[test]
+    fn rejects_malformed_json_array() {
+        for body in [
+            DELETE.to_owned(),
+            format!("[{DELETE}"),
+            format!("[{DELETE},]"),
+            format!("[,{DELETE}]"),
+            format!("[{DELETE}] []"),
+            String::new(),
+        ] {
+            assert!(decode(super::Decoder::json_array(), &body, 3).is_err(), "{body}");
+        }
+    }
+
+    #[test]
+    fn fails_only_oversized_element() {
+        let oversized: String = format!(
+            r#"{{"op":"create","genre":"history","title":"{}","page_count":1}}"#,
+            "a".repeat(super::ITEM_MAX_BYTES)
+        );
+        let body: String = format!("[{CREATE},{oversized},{DELETE}]");
+
+        let items: Vec<super::Item> = decode(super::Decoder::json_array(), &body, 1000).unwrap();
+        assert_eq!(
+            frames(items),
+            vec![
+                Ok(CREATE.to_owned()),
+                Err(format!("Operation exceeds {} bytes", super::ITEM_MAX_BYTES)),
+                Ok(DELETE.to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn splits_ndjson_lines() {
+        let oversized: String = format!(
+            r#"{{"op":"create","genre":"history","title":"{}","page_count":1}}"#,
+            "a".repeat(super::ITEM_MAX_BYTES)
+        );
+        let body: String = format!("{CREATE}\n\n  \r\n{oversized}\n{DELETE}");
+
+        let items: Vec<super::Item> = decode(super::Decoder::ndjson(), &body, 1000).unwrap();
+        assert_eq!(
+            frames(items),
+            vec![
+                Ok(CREATE.to_owned()),
+                Err(format!("Line exceeds {} bytes", super::ITEM_MAX_BYTES)),
+                Ok(DELETE.to_owned()),
+            ]
+        );
+    }
+}
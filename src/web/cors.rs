@@ -0,0 +1,97 @@
+//! Cross-Origin Resource Sharing (CORS), so that browser apps served from other
+//! origins can call the API. The policy is read from a JSON file that is
+//! reloaded when it changes.
+
+/// Contents of the policy file, e.g.:
+///
+/// ```json
+/// {
+///   "origins": ["https://app.example"],
+///   "methods": ["GET", "POST", "DELETE"],
+///   "headers": ["authorization", "content-type"],
+///   "credentials": false,
+///   "max_age_secs": 600
+/// }
+/// ```
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Policy {
+    /// Origins like `https://app.example`, or `*` for any origin.
+    pub origins: Vec<String>,
+    pub methods: Vec<String>,
+    /// Request headers that clients may send, in addition to the ones that
+    /// are always allowed, e.g. `Accept`.
+    pub headers: Vec<String>,
+    /// Whether browsers may send cookies and other credentials along.
+    pub credentials: bool,
+    /// How long browsers may cache the result of a preflight request.
+    pub max_age_secs: u32,
+}
+
+impl Policy {
+    fn parse(contents: &[u8]) -> Result<Self, String> {
+        let mut policy: Policy = serde_json::from_slice(contents).map_err(|err| err.to_string())?;
+
+        if policy.credentials && policy.origins.iter().any(|origin| origin == "*") {
+            return Err("Origin \"*\" cannot be combined with credentials".to_owned());
+        }
+        for method in &mut policy.methods {
+            if let Err(err) = axum::http::Method::from_bytes(method.as_bytes()) {
+                return Err(format!("Invalid method {method:?}: {err}"));
+            }
+            *method = method.to_ascii_uppercase();
+        }
+        for header in &mut policy.headers {
+            if let Err(err) = axum::http::HeaderName::from_bytes(header.as_bytes()) {
+                return Err(format!("Invalid header {header:?}: {err}"));
+            }
+            *header = header.to_ascii_lowercase();
+        }
+
+        Ok(policy)
+    }
+
+    pub fn allows_origin(&self, origin: &str) -> bool {
+        self.origins.iter().any(|allowed| allowed == "*" || allowed == origin)
+    }
+
+    pub fn allows_method(&self, method: &str) -> bool {
+        self.methods.iter().any(|allowed| allowed == method)
+    }
+
+    /// `headers` is the comma separated list of header
+    /// `Access-Control-Request-Headers`. Fails with the first header that's
+    /// not allowed.
+    pub fn allows_headers(&self, headers: &str) -> Result<(), String> {
+        for header in headers.split(',').map(str::trim).filter(|header| !header.is_empty()) {
+            let header: String = header.to_ascii_lowercase();
+            if !self.headers.contains(&header) {
+                return Err(header);
+            }
+        }
+        Ok(())
+    }
+}
+
+pub struct Cors {
+    policy: crate::reload::Watched<Policy>,
+}
+
+impl Cors {
+    /// Reads environment variable `CORS_FILE`: Path of the policy file, which
+    /// is reloaded when it changes. Unset by default, i.e. requests from other
+    /// origins are not allowed by browsers.
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let Some(path) = std::env::var_os("CORS_FILE") else {
+            return Ok(None);
+        };
+
+        Ok(Some(Self {
+            policy: crate::reload::Watched::load(path.into(), Policy::parse)?,
+        }))
+    }
+
+    pub fn policy(&self) -> std::sync::Arc<Policy> {
+        self.policy.get()
+    }
+}
@@ -1,27 +1,104 @@
+/// Seconds that clients are told to wait before retrying a shed request.
+const SHED_RETRY_AFTER_SECS: u64 = 1;
+
+pub enum Error {
+    /// No room freed up in the database actor's queue within the admission
+    /// timeout, i.e. the database is not keeping up.
+    Shed,
+    /// Logged where it occurred.
+    Failed,
+}
+
+impl axum::response::IntoResponse for Error {
+    /// 503 Service Unavailable with header `Retry-After` for shed requests,
+    /// 500 Internal Server Error otherwise.
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            Error::Shed => (
+                axum::http::StatusCode::SERVICE_UNAVAILABLE,
+                [(axum::http::header::RETRY_AFTER, SHED_RETRY_AFTER_SECS.to_string())],
+            )
+                .into_response(),
+            Error::Failed => axum::http::StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct DatabaseClient {
-    tx_query: tokio::sync::mpsc::Sender<crate::db::Query>,
+    tx_query: tokio::sync::mpsc::Sender<crate::db::Envelope>,
+    admission_timeout: std::time::Duration,
 }
 
 impl DatabaseClient {
-    pub fn new(tx_query: tokio::sync::mpsc::Sender<crate::db::Query>) -> Self {
-        Self { tx_query }
+    pub fn new(
+        tx_query: tokio::sync::mpsc::Sender<crate::db::Envelope>,
+        admission_timeout: std::time::Duration,
+    ) -> Self {
+        Self {
+            tx_query,
+            admission_timeout,
+        }
+    }
+
+    /// Number of queries waiting in the channel in front of the database actor.
+    pub fn queue_depth(&self) -> usize {
+        self.tx_query.max_capacity() - self.tx_query.capacity()
+    }
+
+    /// Hands the query over to the database actor, once there's room for it in
+    /// the channel, along with the context of the request it's sent for, incl.
+    /// its deadline. Gives up with [`Error::Shed`] if there's no room within
+    /// the admission timeout.
+    async fn send(
+        &mut self,
+        request_id: &crate::web::middleware::RequestId,
+        db_query: crate::db::Query,
+    ) -> Result<(), Error> {
+        use tracing::Instrument;
+
+        let send_span: tracing::Span = tracing::info_span!("db_client.send", db.query = db_query.name());
+        let permit =
+            match tokio::time::timeout(self.admission_timeout, self.tx_query.reserve().instrument(send_span)).await {
+                Ok(Ok(n)) => n,
+                Ok(Err(err)) => {
+                    log::error!("{err}");
+                    return Err(Error::Failed);
+                }
+                Err(_elapsed) => {
+                    crate::metrics::METRICS.db_shed_requests.inc();
+                    log::warn!(
+                        "Service unavailable: Shed query {}: Database queue stayed full for {:?}",
+                        db_query.name(),
+                        self.admission_timeout
+                    );
+                    return Err(Error::Shed);
+                }
+            };
+        let deadline: Option<std::time::Instant> = crate::web::middleware::DEADLINE.try_with(|deadline| *deadline).ok();
+        permit.send(crate::db::Envelope::new(
+            db_query,
+            Some(request_id.to_string()),
+            deadline,
+        ));
+
+        Ok(())
     }
 
-    pub async fn select_books_not_removed(&mut self) -> Result<Vec<crate::db::schema_v1::Book>, ()> {
+    pub async fn select_books_not_removed(
+        &mut self,
+        request_id: &crate::web::middleware::RequestId,
+    ) -> Result<Vec<crate::db::schema_v1::Book>, Error> {
         let (tx, rx) = tokio::sync::oneshot::channel();
         let db_query: crate::db::Query = crate::db::Query::SelectBooksNotRemoved { respond_to: tx };
 
-        if let Err(err) = self.tx_query.send(db_query).await {
-            log::error!("{err}");
-            return Err(());
-        };
+        self.send(request_id, db_query).await?;
 
         let db_actor_response = match rx.await {
             Ok(n) => n,
             Err(err) => {
                 log::error!("{err}");
-                return Err(());
+                return Err(Error::Failed);
             }
         };
 
@@ -29,58 +106,62 @@ impl DatabaseClient {
             Ok(n) => n,
             Err(err) => {
                 log::error!("{err}");
-                return Err(());
+                return Err(Error::Failed);
             }
         };
 
         Ok(books)
     }
 
-    pub async fn select_book_by_id(&mut self, book_id: uuid::Uuid) -> Result<crate::db::schema_v1::Book, ()> {
+    /// `None` if there's no such book.
+    pub async fn select_book_by_id(
+        &mut self,
+        request_id: &crate::web::middleware::RequestId,
+        book_id: uuid::Uuid,
+    ) -> Result<Option<crate::db::schema_v1::Book>, Error> {
         let (tx, rx) = tokio::sync::oneshot::channel();
         let db_query: crate::db::Query = crate::db::Query::SelectBookById {
             respond_to: tx,
             book_id,
         };
 
-        if let Err(err) = self.tx_query.send(db_query).await {
-            log::error!("{err}");
-            return Err(());
-        };
+        self.send(request_id, db_query).await?;
 
         let db_actor_response = match rx.await {
             Ok(n) => n,
             Err(err) => {
                 log::error!("{err}");
-                return Err(());
+                return Err(Error::Failed);
             }
         };
 
-        let book: crate::db::schema_v1::Book = match db_actor_response {
-            Ok(n) => n,
+        let book: Option<crate::db::schema_v1::Book> = match db_actor_response {
+            Ok(n) => Some(n),
+            Err(diesel::result::Error::NotFound) => None,
             Err(err) => {
                 log::error!("{err}");
-                return Err(());
+                return Err(Error::Failed);
             }
         };
 
         Ok(book)
     }
 
-    pub async fn insert_book(&mut self, book: crate::db::schema_v1::Book) -> Result<usize, ()> {
+    pub async fn insert_book(
+        &mut self,
+        request_id: &crate::web::middleware::RequestId,
+        book: crate::db::schema_v1::Book,
+    ) -> Result<usize, Error> {
         let (tx, rx) = tokio::sync::oneshot::channel();
         let db_query: crate::db::Query = crate::db::Query::InsertBook { respond_to: tx, book };
 
-        if let Err(err) = self.tx_query.send(db_query).await {
-            log::error!("{err}");
-            return Err(());
-        };
+        self.send(request_id, db_query).await?;
 
         let db_actor_response = match rx.await {
             Ok(n) => n,
             Err(err) => {
                 log::error!("{err}");
-                return Err(());
+                return Err(Error::Failed);
             }
         };
 
@@ -88,35 +169,301 @@ impl DatabaseClient {
             Ok(n) => n,
             Err(err) => {
                 log::error!("{err}");
-                return Err(());
+                return Err(Error::Failed);
             }
         };
 
         Ok(rows_affected)
     }
 
+    pub async fn insert_book_idempotently(
+        &mut self,
+        request_id: &crate::web::middleware::RequestId,
+        book: crate::db::schema_v1::Book,
+        idempotency_key: crate::db::schema_v1::IdempotencyKey,
+    ) -> Result<crate::db::Idempotent, Error> {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let db_query: crate::db::Query = crate::db::Query::InsertBookIdempotently {
+            respond_to: tx,
+            book,
+            idempotency_key,
+        };
+
+        self.send(request_id, db_query).await?;
+
+        let db_actor_response = match rx.await {
+            Ok(n) => n,
+            Err(err) => {
+                log::error!("{err}");
+                return Err(Error::Failed);
+            }
+        };
+
+        let idempotent: crate::db::Idempotent = match db_actor_response {
+            Ok(n) => n,
+            Err(err) => {
+                log::error!("{err}");
+                return Err(Error::Failed);
+            }
+        };
+
+        Ok(idempotent)
+    }
+
+    /// Responds with the book as stored and whether it was inserted, or `None`
+    /// if the existing book can't be replaced, see
+    /// [`crate::db::Query::UpsertBook`].
+    pub async fn upsert_book(
+        &mut self,
+        request_id: &crate::web::middleware::RequestId,
+        book: crate::db::schema_v1::Book,
+        authority: crate::db::Authority,
+    ) -> Result<Option<(crate::db::schema_v1::Book, bool)>, Error> {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let db_query: crate::db::Query = crate::db::Query::UpsertBook {
+            respond_to: tx,
+            book,
+            authority,
+        };
+
+        self.send(request_id, db_query).await?;
+
+        let db_actor_response = match rx.await {
+            Ok(n) => n,
+            Err(err) => {
+                log::error!("{err}");
+                return Err(Error::Failed);
+            }
+        };
+
+        let upserted: Option<(crate::db::schema_v1::Book, bool)> = match db_actor_response {
+            Ok(n) => n,
+            Err(err) => {
+                log::error!("{err}");
+                return Err(Error::Failed);
+            }
+        };
+
+        Ok(upserted)
+    }
+
+    /// Responds with the outcome of each operation, in order.
+    pub async fn bulk_books(
+        &mut self,
+        request_id: &crate::web::middleware::RequestId,
+        operations: Vec<crate::db::bulk::Operation>,
+        authority: crate::db::Authority,
+        mode: crate::db::bulk::Mode,
+    ) -> Result<Vec<crate::db::bulk::Outcome>, Error> {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let db_query: crate::db::Query = crate::db::Query::BulkBooks {
+            respond_to: tx,
+            operations,
+            authority,
+            mode,
+        };
+
+        self.send(request_id, db_query).await?;
+
+        let db_actor_response = match rx.await {
+            Ok(n) => n,
+            Err(err) => {
+                log::error!("{err}");
+                return Err(Error::Failed);
+            }
+        };
+
+        let outcomes: Vec<crate::db::bulk::Outcome> = match db_actor_response {
+            Ok(n) => n,
+            Err(err) => {
+                log::error!("{err}");
+                return Err(Error::Failed);
+            }
+        };
+
+        Ok(outcomes)
+    }
+
     pub async fn update_book_set_removed(
         &mut self,
+        request_id: &crate::web::middleware::RequestId,
         book_id: uuid::Uuid,
         removed_at_utc: chrono::DateTime<chrono::Utc>,
-    ) -> Result<usize, ()> {
+        authority: crate::db::Authority,
+    ) -> Result<usize, Error> {
         let (tx, rx) = tokio::sync::oneshot::channel();
         let db_query: crate::db::Query = crate::db::Query::UpdateBookSetRemovedById {
             respond_to: tx,
             book_id,
             removed_at_utc,
+            authority,
+        };
+
+        self.send(request_id, db_query).await?;
+
+        let db_actor_response = match rx.await {
+            Ok(n) => n,
+            Err(err) => {
+                log::error!("{err}");
+                return Err(Error::Failed);
+            }
         };
 
-        if let Err(err) = self.tx_query.send(db_query).await {
-            log::error!("{err}");
-            return Err(());
+        let rows_affected: usize = match db_actor_response {
+            Ok(n) => n,
+            Err(err) => {
+                log::error!("{err}");
+                return Err(Error::Failed);
+            }
         };
 
+        Ok(rows_affected)
+    }
+
+    /// Returns an error if the round trip fails or if the database has
+    /// migrations that have not been applied yet.
+    pub async fn ping(&mut self, request_id: &crate::web::middleware::RequestId) -> Result<(), Error> {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let db_query: crate::db::Query = crate::db::Query::Ping { respond_to: tx };
+
+        self.send(request_id, db_query).await?;
+
+        let db_actor_response = match rx.await {
+            Ok(n) => n,
+            Err(err) => {
+                log::error!("{err}");
+                return Err(Error::Failed);
+            }
+        };
+
+        let has_pending_migrations: bool = match db_actor_response {
+            Ok(n) => n,
+            Err(err) => {
+                log::error!("{err}");
+                return Err(Error::Failed);
+            }
+        };
+
+        if has_pending_migrations {
+            log::warn!("Database has pending migrations");
+            return Err(Error::Failed);
+        }
+
+        Ok(())
+    }
+
+    pub async fn insert_api_key(
+        &mut self,
+        request_id: &crate::web::middleware::RequestId,
+        api_key: crate::db::schema_v1::ApiKey,
+    ) -> Result<usize, Error> {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let db_query: crate::db::Query = crate::db::Query::InsertApiKey {
+            respond_to: tx,
+            api_key,
+        };
+
+        self.send(request_id, db_query).await?;
+
+        let db_actor_response = match rx.await {
+            Ok(n) => n,
+            Err(err) => {
+                log::error!("{err}");
+                return Err(Error::Failed);
+            }
+        };
+
+        let rows_affected: usize = match db_actor_response {
+            Ok(n) => n,
+            Err(err) => {
+                log::error!("{err}");
+                return Err(Error::Failed);
+            }
+        };
+
+        Ok(rows_affected)
+    }
+
+    pub async fn select_api_keys(
+        &mut self,
+        request_id: &crate::web::middleware::RequestId,
+    ) -> Result<Vec<crate::db::schema_v1::ApiKey>, Error> {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let db_query: crate::db::Query = crate::db::Query::SelectApiKeys { respond_to: tx };
+
+        self.send(request_id, db_query).await?;
+
+        let db_actor_response = match rx.await {
+            Ok(n) => n,
+            Err(err) => {
+                log::error!("{err}");
+                return Err(Error::Failed);
+            }
+        };
+
+        let api_keys: Vec<crate::db::schema_v1::ApiKey> = match db_actor_response {
+            Ok(n) => n,
+            Err(err) => {
+                log::error!("{err}");
+                return Err(Error::Failed);
+            }
+        };
+
+        Ok(api_keys)
+    }
+
+    pub async fn select_api_key_not_revoked_by_hash(
+        &mut self,
+        request_id: &crate::web::middleware::RequestId,
+        key_hash: Vec<u8>,
+    ) -> Result<Option<crate::db::schema_v1::ApiKey>, Error> {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let db_query: crate::db::Query = crate::db::Query::SelectApiKeyNotRevokedByHash {
+            respond_to: tx,
+            key_hash,
+        };
+
+        self.send(request_id, db_query).await?;
+
+        let db_actor_response = match rx.await {
+            Ok(n) => n,
+            Err(err) => {
+                log::error!("{err}");
+                return Err(Error::Failed);
+            }
+        };
+
+        let api_key: Option<crate::db::schema_v1::ApiKey> = match db_actor_response {
+            Ok(n) => n,
+            Err(err) => {
+                log::error!("{err}");
+                return Err(Error::Failed);
+            }
+        };
+
+        Ok(api_key)
+    }
+
+    pub async fn update_api_key_set_revoked(
+        &mut self,
+        request_id: &crate::web::middleware::RequestId,
+        api_key_id: uuid::Uuid,
+        revoked_at_utc: chrono::DateTime<chrono::Utc>,
+    ) -> Result<usize, Error> {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let db_query: crate::db::Query = crate::db::Query::UpdateApiKeySetRevokedById {
+            respond_to: tx,
+            api_key_id,
+            revoked_at_utc,
+        };
+
+        self.send(request_id, db_query).await?;
+
         let db_actor_response = match rx.await {
             Ok(n) => n,
             Err(err) => {
                 log::error!("{err}");
-                return Err(());
+                return Err(Error::Failed);
             }
         };
 
@@ -124,10 +471,46 @@ impl DatabaseClient {
             Ok(n) => n,
             Err(err) => {
                 log::error!("{err}");
-                return Err(());
+                return Err(Error::Failed);
             }
         };
 
         Ok(rows_affected)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    #[tokio::test]
+    async fn sheds_query_while_queue_stays_full() {
+        let mut config: crate::web::Config = crate::web::testing::config();
+        config.db_admission_timeout = std::time::Duration::from_millis(50);
+        /*
+         * No database actor receives from the mailbox, which is filled up
+         * beforehand.
+         */
+        let db_mailbox: crate::db::Mailbox = crate::db::Mailbox::open(1);
+        let (respond_to, _rx) = tokio::sync::oneshot::channel();
+        let waiting: crate::db::Envelope = crate::db::Envelope::new(crate::db::Query::Ping { respond_to }, None, None);
+        if db_mailbox.get_handle().try_send(waiting).is_err() {
+            panic!("Mailbox is full already");
+        }
+        let address: std::net::SocketAddr = crate::web::testing::serve(&config, &db_mailbox).await;
+        let shed_before: u64 = crate::metrics::METRICS.db_shed_requests.get();
+
+        let started_at: std::time::Instant = std::time::Instant::now();
+        let response: crate::web::testing::Response = crate::web::testing::send(
+            address,
+            "GET",
+            "/admin/api-keys",
+            &[&format!("authorization: Bearer {}", crate::web::testing::ADMIN_TOKEN)],
+            "",
+        )
+        .await;
+
+        assert_eq!(response.status, 503);
+        assert_eq!(response.header("retry-after"), Some("1"));
+        assert!(started_at.elapsed() >= config.db_admission_timeout);
+        assert!(crate::metrics::METRICS.db_shed_requests.get() > shed_before);
+    }
+}
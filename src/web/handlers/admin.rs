@@ -0,0 +1,276 @@
+//! Administration of the running process and of API keys. All handlers
+//! require scope `books:admin`, and respond with 401 Unauthorized or 403
+//! Forbidden otherwise.
+//!
+//! Handlers that query the database respond with 503 Service Unavailable when
+//! the request is shed, like in [`crate::web::handlers::books_v1::post_one`].
+
+/// Replace the log levels at runtime, optionally reverting to the previous ones
+/// after `revert_after_secs`.
+///
+/// **Cases implemented manually**:
+///
+/// - 204 No Content: Log levels changed.
+///
+/// - 400 Bad Request:
+///
+///   - Some level was not one of `off`, `error`, `warn`, `info`, `debug` or
+///     `trace`.
+///
+///   - Some module was not a path of modules like `db` or `web::handlers`.
+///
+/// - 500 Internal Server Error: Rebuilding the logger config failed, e.g.
+///   because the log file could not be reopened. Previous levels stay in effect.
+///
+/// **Cases provided automatically**: 400, 415 and 422 for payloads that aren't
+/// JSON of the expected shape, like in [`crate::web::handlers::books_v1`].
+pub async fn put_log_level(
+    axum::extract::State(shared): axum::extract::State<crate::web::Shared>,
+    principal: crate::web::auth::Principal,
+    axum::Json(body): axum::Json<api::LogLevels>,
+) -> axum::response::Response {
+    use axum::response::IntoResponse;
+
+    if let Err(status) = principal.authorize(crate::web::auth::Scope::Admin) {
+        return status.into_response();
+    }
+
+    let root: log::LevelFilter = match body.root.parse() {
+        Ok(n) => n,
+        Err(err) => {
+            log::error!("Bad request: Invalid root log level {:?}: {err}", body.root);
+            return axum::http::StatusCode::BAD_REQUEST.into_response();
+        }
+    };
+
+    let mut modules: std::collections::BTreeMap<String, log::LevelFilter> = std::collections::BTreeMap::new();
+    for (module, level) in body.modules {
+        let is_module_path: bool = module
+            .split("::")
+            .all(|segment| !segment.is_empty() && segment.bytes().all(|b| b.is_ascii_lowercase() || b == b'_'));
+        if !is_module_path {
+            log::error!("Bad request: Invalid module {module:?}");
+            return axum::http::StatusCode::BAD_REQUEST.into_response();
+        }
+        let level: log::LevelFilter = match level.parse() {
+            Ok(n) => n,
+            Err(err) => {
+                log::error!("Bad request: Invalid log level {level:?} of module {module}: {err}");
+                return axum::http::StatusCode::BAD_REQUEST.into_response();
+            }
+        };
+        modules.insert(module, level);
+    }
+
+    let levels: crate::logg::Levels = crate::logg::Levels { root, modules };
+    let (previous, change) = match shared.log_levels.set(levels) {
+        Ok(n) => n,
+        Err(()) => {
+            return axum::http::StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    log::info!(principal = principal.id.as_str(); "Changed log levels: root {root}");
+
+    if let Some(secs) = body.revert_after_secs {
+        let revert_after: std::time::Duration = std::time::Duration::from_secs(secs);
+        log::info!("Reverting log levels in {revert_after:?}");
+        let log_levels: std::sync::Arc<crate::logg::LevelControl> = shared.log_levels.clone();
+        tokio::task::spawn(async move {
+            tokio::time::sleep(revert_after).await;
+            log_levels.revert(change, previous);
+        });
+    }
+
+    axum::http::StatusCode::NO_CONTENT.into_response()
+}
+
+/// Issue a new API key. The key itself is only ever included in this response,
+/// as only its hash is stored.
+///
+/// **Cases implemented manually**:
+///
+/// - 201 Created: Key issued, included in the response body.
+///
+/// - 400 Bad Request:
+///
+///   - Name was empty or longer than 252 characters.
+///
+///   - Some scope was not one of `books:read`, `books:write` or `books:admin`.
+///
+/// - 409 Conflict: Some key that is not revoked has the same name.
+///
+/// - 500 Internal Server Error: Generating or storing the key failed.
+pub async fn post_one_api_key(
+    axum::extract::State(mut shared): axum::extract::State<crate::web::Shared>,
+    axum::Extension(request_id): axum::Extension<crate::web::middleware::RequestId>,
+    principal: crate::web::auth::Principal,
+    axum::Json(body): axum::Json<api::ApiKeyUnpopulated>,
+) -> Result<(axum::http::StatusCode, axum::Json<api::ApiKeyIssued>), axum::response::Response> {
+    use axum::response::IntoResponse;
+
+    if let Err(status) = principal.authorize(crate::web::auth::Scope::Admin) {
+        return Err(status.into_response());
+    }
+
+    if body.name.is_empty() || body.name.chars().count() > crate::web::auth::API_KEY_NAME_MAX_CHARS {
+        log::error!(
+            "Bad request: API key name must be 1 to {} characters",
+            crate::web::auth::API_KEY_NAME_MAX_CHARS
+        );
+        return Err(axum::http::StatusCode::BAD_REQUEST.into_response());
+    }
+    let mut scopes: Vec<crate::web::auth::Scope> = Vec::new();
+    for scope in &body.scopes {
+        match scope.parse() {
+            Ok(n) => scopes.push(n),
+            Err(err) => {
+                log::error!("Bad request: {err}");
+                return Err(axum::http::StatusCode::BAD_REQUEST.into_response());
+            }
+        }
+    }
+
+    let key: String = match crate::web::auth::generate_api_key() {
+        Ok(n) => n,
+        Err(err) => {
+            log::error!("Generating API key failed: {err}");
+            return Err(axum::http::StatusCode::INTERNAL_SERVER_ERROR.into_response());
+        }
+    };
+    let api_key: crate::db::schema_v1::ApiKey = crate::db::schema_v1::ApiKey {
+        id: uuid::Uuid::new_v4(),
+        created_at_utc: chrono::Utc::now().naive_utc(),
+        revoked_at_utc: None,
+
+        name: body.name,
+        key_hash: crate::web::auth::hash_api_key(&key),
+        scopes: scopes.iter().map(|scope| scope.to_string()).collect(),
+    };
+    let issued: api::ApiKeyIssued = api::ApiKeyIssued {
+        key,
+        api_key: api_key.clone().into(),
+    };
+
+    let rows_affected: usize = match shared.db_client.insert_api_key(&request_id, api_key).await {
+        Ok(n) => n,
+        Err(err) => {
+            return Err(err.into_response());
+        }
+    };
+    if rows_affected == 0 {
+        log::error!("Conflict: API key named {:?} exists already", issued.api_key.name);
+        return Err(axum::http::StatusCode::CONFLICT.into_response());
+    }
+    log::info!(principal = principal.id.as_str(); "Issued API key {}", issued.api_key.id);
+
+    Ok((axum::http::StatusCode::CREATED, axum::Json(issued)))
+}
+
+/// List all API keys, including revoked ones, without the keys themselves.
+pub async fn get_all_api_keys(
+    axum::extract::State(mut shared): axum::extract::State<crate::web::Shared>,
+    axum::Extension(request_id): axum::Extension<crate::web::middleware::RequestId>,
+    principal: crate::web::auth::Principal,
+) -> Result<axum::Json<Vec<api::ApiKeyPopulated>>, axum::response::Response> {
+    use axum::response::IntoResponse;
+
+    if let Err(status) = principal.authorize(crate::web::auth::Scope::Admin) {
+        return Err(status.into_response());
+    }
+
+    let api_keys: Vec<crate::db::schema_v1::ApiKey> = match shared.db_client.select_api_keys(&request_id).await {
+        Ok(n) => n,
+        Err(err) => {
+            return Err(err.into_response());
+        }
+    };
+
+    Ok(axum::Json(api_keys.into_iter().map(|n| n.into()).collect()))
+}
+
+/// Revoke an API key, i.e. stop accepting it from then on.
+///
+/// - 204 No Content: Revoked successfully.
+///
+/// - 404 Not Found: No such key, or the key was revoked already.
+pub async fn delete_one_api_key_by_id(
+    axum::extract::State(mut shared): axum::extract::State<crate::web::Shared>,
+    axum::Extension(request_id): axum::Extension<crate::web::middleware::RequestId>,
+    principal: crate::web::auth::Principal,
+    axum::extract::Path(api_key_id): axum::extract::Path<uuid::Uuid>,
+) -> axum::response::Response {
+    use axum::response::IntoResponse;
+
+    if let Err(status) = principal.authorize(crate::web::auth::Scope::Admin) {
+        return status.into_response();
+    }
+
+    let rows_affected: usize = match shared
+        .db_client
+        .update_api_key_set_revoked(&request_id, api_key_id, chrono::Utc::now())
+        .await
+    {
+        Ok(n) => n,
+        Err(err) => {
+            return err.into_response();
+        }
+    };
+
+    if rows_affected == 0 {
+        log::error!("Not found: Cannot DELETE: No API key {api_key_id} that is not revoked");
+        return axum::http::StatusCode::NOT_FOUND.into_response();
+    }
+    log::info!(principal = principal.id.as_str(); "Revoked API key {api_key_id}");
+
+    axum::http::StatusCode::NO_CONTENT.into_response()
+}
+
+mod api {
+    #[derive(serde::Deserialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct ApiKeyUnpopulated {
+        /// Describes the holder of the key.
+        pub name: String,
+        /// E.g. `books:read`.
+        pub scopes: Vec<String>,
+    }
+
+    #[derive(serde::Serialize)]
+    pub struct ApiKeyPopulated {
+        pub id: uuid::Uuid,
+        pub name: String,
+        pub scopes: Vec<String>,
+        pub created_at_utc: chrono::NaiveDateTime,
+        pub revoked_at_utc: Option<chrono::NaiveDateTime>,
+    }
+
+    #[derive(serde::Serialize)]
+    pub struct ApiKeyIssued {
+        /// To be sent in header `Authorization: Bearer <key>`.
+        pub key: String,
+        #[serde(flatten)]
+        pub api_key: ApiKeyPopulated,
+    }
+
+    impl From<crate::db::schema_v1::ApiKey> for ApiKeyPopulated {
+        fn from(db: crate::db::schema_v1::ApiKey) -> Self {
+            Self {
+                id: db.id,
+                name: db.name,
+                scopes: db.scopes,
+                created_at_utc: db.created_at_utc,
+                revoked_at_utc: db.revoked_at_utc,
+            }
+        }
+    }
+
+    #[derive(serde::Deserialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct LogLevels {
+        pub root: String,
+        /// Levels by module path relative to the crate, e.g. `db`.
+        #[serde(default)]
+        pub modules: std::collections::BTreeMap<String, String>,
+        pub revert_after_secs: Option<u64>,
+    }
+}
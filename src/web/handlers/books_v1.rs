@@ -3,23 +3,54 @@
 //! (e.g. `by_id`).
 
 /// Create a new book, i.e. INSERT a new, non-removed book into the database,
-/// using a randomly generated ID.
+/// using a generated UUIDv7, which is ordered by creation time for the locality
+/// of the primary key index. The authenticated principal becomes the owner of
+/// the book.
+///
+/// With header `Idempotency-Key`, the request can be retried safely: Retries
+/// of the same request with the same key get the response of the first one,
+/// with header `Idempotent-Replayed: true`, instead of creating another book.
+/// See [`crate::web::idempotency`].
 ///
 /// **Cases implemented manually**:
 ///
-/// - 204 No Content: Created succesfully.
+/// - 204 No Content: Created succesfully, or replayed.
 /// 
+/// - 401 Unauthorized: Request didn't carry valid credentials.
+///
+/// - 403 Forbidden: Client lacks scope `books:write`.
+///
+/// - 400 Bad Request: Header `Idempotency-Key` was not 1 to 256 visible ASCII
+///   characters.
+///
+/// - 422 Unprocessable Entity, with a response body listing the errors of each
+///   field, see [`crate::web::validation::Errors`]:
+///
+///   - Book's title was empty after trimming surrounding whitespace, contained
+///     control characters or was longer than 256 characters.
+///
+///   - Book's page count was outside of [`api::PAGE_COUNT`].
+///
+///   - Header `Idempotency-Key` was already used for a request with a
+///     different genre or payload.
+///
 /// - 500 Internal Server Error:
 ///
 ///   - Database schema in actual PostgreSQL instance doesn't match the one
 ///     declared in the program.
 ///
+/// - 503 Service Unavailable: Database is not keeping up with the load, and
+///   the request was shed. Header `Retry-After` tells when to try again.
+///
 /// **Cases provided automatically**, thanks to the ergonomics of the used
 /// libraries (_axum_, _serde_, etc.):
 ///
 /// - 415 Unsupported Media Type:
 ///
 ///   - Request didn't specify header `Content-Type: application/json`
+///
+///   - Request specified a `Content-Encoding` other than `gzip`, `br` or
+///     `zstd`.
 /// 
 /// - 400 Bad Request:
 ///
@@ -30,6 +61,12 @@
 ///
 ///   - Request's body payload was not deserializable as JSON.
 ///
+///   - Request's body payload was not decodable per its `Content-Encoding`.
+///
+/// - 413 Payload Too Large: Request's body payload exceeded the limit, see
+///   [`crate::web::RequestBodyLimit`], or the limit after decompression, see
+///   [`crate::web::ContentEncoding`].
+///
 /// - 422 Unprocessable Entity:
 ///
 ///   - Request's JSON payload contained some unexpected field.
@@ -40,29 +77,448 @@
 ///     or as some non-numeric value, or its title was not a string etc.
 pub async fn post_one(
     axum::extract::State(mut shared): axum::extract::State<crate::web::Shared>,
+    axum::Extension(request_id): axum::Extension<crate::web::middleware::RequestId>,
+    principal: crate::web::auth::Principal,
     axum::extract::Path(genre): axum::extract::Path<api::Genre>,
-    axum::Json(book): axum::Json<api::BookUnpopulated>,
-) -> axum::http::StatusCode {
-    let id: uuid::Uuid = uuid::Uuid::new_v4();
-    let book: crate::db::schema_v1::Book = book.populate(id, genre);
+    headers: axum::http::HeaderMap,
+    crate::web::validation::Valid(book): crate::web::validation::Valid<api::BookUnpopulated>,
+) -> axum::response::Response {
+    use axum::response::IntoResponse;
 
-    let _rows_affected: usize = match shared.db_client.insert_book(book).await {
+    if let Err(status) = principal.authorize(crate::web::auth::Scope::Write) {
+        return status.into_response();
+    }
+    let idempotency_key: Option<String> = match crate::web::idempotency::key(&headers) {
         Ok(n) => n,
-        Err(_) => {
-            return axum::http::StatusCode::INTERNAL_SERVER_ERROR;
+        Err(err) => {
+            log::error!("Bad request: {err}");
+            return axum::http::StatusCode::BAD_REQUEST.into_response();
+        }
+    };
+
+    let request_hash: Vec<u8> = crate::web::idempotency::request_hash(&[
+        genre.to_string().as_bytes(),
+        book.title.as_bytes(),
+        &book.page_count.to_be_bytes(),
+    ]);
+    let owner: String = principal.owner.clone();
+    let id: uuid::Uuid = uuid::Uuid::now_v7();
+    let book: crate::db::schema_v1::Book = book.populate(id, genre, principal.owner);
+
+    let Some(key) = idempotency_key else {
+        let _rows_affected: usize = match shared.db_client.insert_book(&request_id, book).await {
+            Ok(n) => n,
+            Err(err) => {
+                return err.into_response();
+            }
+        };
+        return axum::http::StatusCode::NO_CONTENT.into_response();
+    };
+
+    let now: chrono::DateTime<chrono::Utc> = chrono::Utc::now();
+    let stored: crate::db::schema_v1::IdempotencyKey = crate::db::schema_v1::IdempotencyKey {
+        principal: owner,
+        key,
+        created_at_utc: now.naive_utc(),
+        expires_at_utc: (now + shared.idempotency_key_ttl.0).naive_utc(),
+
+        request_hash,
+        response_status: axum::http::StatusCode::NO_CONTENT.as_u16() as i16,
+        response_body: Vec::new(),
+    };
+    let idempotent: crate::db::Idempotent = match shared
+        .db_client
+        .insert_book_idempotently(&request_id, book, stored)
+        .await
+    {
+        Ok(n) => n,
+        Err(err) => {
+            return err.into_response();
+        }
+    };
+
+    match idempotent {
+        crate::db::Idempotent::Inserted => axum::http::StatusCode::NO_CONTENT.into_response(),
+        crate::db::Idempotent::Replayed(stored) => crate::web::idempotency::replay(stored),
+        crate::db::Idempotent::Mismatched => {
+            let mut errors: crate::web::validation::Errors = crate::web::validation::Errors::default();
+            errors.push("Idempotency-Key", "Already used for a different request".to_owned());
+            log::error!("Unprocessable entity: {errors}");
+            errors.into_response()
+        }
+    }
+}
+
+/// Create or replace a book with an ID chosen by the client, e.g. one that an
+/// upstream system already has for it, i.e. INSERT the book or UPDATE the
+/// existing one in a single upsert. The authenticated principal becomes the
+/// owner of a created book. Owners can replace their own books and admins can
+/// replace any book, which is enforced by the database query. Responds with
+/// the book as stored.
+///
+/// **Cases implemented manually**:
+///
+/// - 201 Created: Book didn't exist and was created.
+///
+/// - 200 OK: Book existed and was replaced.
+///
+/// - 401 Unauthorized: Request didn't carry valid credentials.
+///
+/// - 403 Forbidden: Client lacks scope `books:write`, or the book was removed
+///   or is owned by someone else.
+///
+/// - 422 Unprocessable Entity: Book's title or page count were invalid, like in
+///   [`post_one`].
+///
+/// - 503 Service Unavailable: Request was shed, like in [`post_one`].
+///
+/// **Cases provided automatically**: 400, 413, 415 and 422 for payloads that
+/// aren't JSON of the expected shape, like in [`post_one`].
+pub async fn put_one_by_id(
+    axum::extract::State(mut shared): axum::extract::State<crate::web::Shared>,
+    axum::Extension(request_id): axum::Extension<crate::web::middleware::RequestId>,
+    principal: crate::web::auth::Principal,
+    axum::extract::Path(book_id): axum::extract::Path<uuid::Uuid>,
+    crate::web::validation::Valid(book): crate::web::validation::Valid<api::BookReplacement>,
+) -> Result<(axum::http::StatusCode, axum::Json<api::BookPopulated>), axum::response::Response> {
+    use axum::response::IntoResponse;
+
+    if let Err(status) = principal.authorize(crate::web::auth::Scope::Write) {
+        return Err(status.into_response());
+    }
+
+    let authority: crate::db::Authority = principal.authority();
+    let book: crate::db::schema_v1::Book = book.populate(book_id, principal.owner);
+    let (stored, inserted): (crate::db::schema_v1::Book, bool) = match shared
+        .db_client
+        .upsert_book(&request_id, book, authority)
+        .await
+    {
+        Ok(Some(n)) => n,
+        Ok(None) => {
+            log::error!(book_id:% = book_id; "Forbidden: Cannot PUT: Book {book_id} was removed or is not owned by {}", principal.name);
+            return Err(axum::http::StatusCode::FORBIDDEN.into_response());
+        }
+        Err(err) => {
+            return Err(err.into_response());
+        }
+    };
+
+    let status: axum::http::StatusCode = if inserted {
+        axum::http::StatusCode::CREATED
+    } else {
+        axum::http::StatusCode::OK
+    };
+
+    Ok((status, axum::Json(stored.into())))
+}
+
+/// Create, replace and remove many books at once, e.g. for importing a
+/// catalog. The request body is either a JSON array of operations
+/// (`Content-Type: application/json`) or one operation per line
+/// (`Content-Type: application/x-ndjson`), see [`api::BulkOperation`]. The
+/// operations are applied in order, in batches of multi-row statements. The
+/// body is parsed as it arrives, see [`crate::web::bulk_body`], and is subject
+/// to [`crate::web::BulkLimits`] rather than the limits of other requests.
+///
+/// Query parameter `mode` is either:
+///
+/// - `all_or_nothing` (default): Either all operations are applied, or none
+///   are, e.g. if any of them is invalid or refused. They are applied in a
+///   single transaction once the whole body has been received, which keeps
+///   the database busy for its duration.
+///
+/// - `best_effort`: Each operation is applied unless it's invalid, refused or
+///   fails. They are sent to the database in batches while the body is still
+///   being received, so that queries of other requests can run in between.
+///
+/// The response body lists the result of each operation, in order, e.g.
+/// `{"results":[{"status":201,"id":"..."},{"status":403,"id":"..."}]}`. The
+/// status of each is that of the corresponding single request, e.g. 201 for a
+/// created book, 200 for a replaced book, 204 for a removed book, 400 for a
+/// line that's not JSON of the expected shape or a book that was removed
+/// already, 403 like in [`put_one_by_id`] and [`delete_one_by_id`], 404 for
+/// removing a book that doesn't exist, 422 with a
+/// list of errors like in [`post_one`], or 424 Failed Dependency for
+/// operations rolled back in mode `all_or_nothing`.
+///
+/// **Cases implemented manually**:
+///
+/// - 200 OK: Mode was `best_effort`, or all operations were applied.
+///
+/// - 400 Bad Request: Request's body payload was not a JSON array, or, in mode
+///   `all_or_nothing`, the first failed operation removed a book that was
+///   removed already.
+///
+/// - 401 Unauthorized: Request didn't carry valid credentials.
+///
+/// - 403 Forbidden: Client lacks scope `books:write`, or, in mode
+///   `all_or_nothing`, the first failed operation was refused.
+///
+/// - 404 Not Found: In mode `all_or_nothing`, the first failed operation
+///   removed a book that doesn't exist.
+///
+/// - 413 Payload Too Large: Request's body exceeded the limits.
+///
+/// - 415 Unsupported Media Type: Request's `Content-Type` was neither of the
+///   above.
+///
+/// - 422 Unprocessable Entity: In mode `all_or_nothing`, the first failed
+///   operation was invalid.
+///
+/// - 500 Internal Server Error: In mode `all_or_nothing`, some statement
+///   failed.
+///
+/// - 503 Service Unavailable: Request was shed, like in [`post_one`].
+///
+/// In mode `best_effort`, batches that were applied before a failure of the
+/// request as a whole, e.g. 413 or 503, stay applied. The response tells how
+/// many operations were applied.
+///
+/// **Cases provided automatically**: 400 for an unknown `mode`, and 413 for
+/// bodies declared too large up front.
+pub async fn post_bulk(
+    axum::extract::State(mut shared): axum::extract::State<crate::web::Shared>,
+    axum::Extension(request_id): axum::Extension<crate::web::middleware::RequestId>,
+    principal: crate::web::auth::Principal,
+    axum::extract::Query(params): axum::extract::Query<api::BulkParams>,
+    headers: axum::http::HeaderMap,
+    body: axum::body::Body,
+) -> Result<(axum::http::StatusCode, axum::Json<api::BulkResults>), axum::response::Response> {
+    use axum::response::IntoResponse;
+    use futures_util::StreamExt;
+
+    if let Err(status) = principal.authorize(crate::web::auth::Scope::Write) {
+        return Err(status.into_response());
+    }
+
+    let content_type: &str = headers
+        .get(axum::http::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    let media_type: &str = content_type.split(';').next().unwrap_or_default().trim();
+    let mut decoder: crate::web::bulk_body::Decoder = match media_type {
+        "application/json" => crate::web::bulk_body::Decoder::json_array(),
+        "application/x-ndjson" => crate::web::bulk_body::Decoder::ndjson(),
+        other => {
+            log::error!("Unsupported media type: {other:?}");
+            return Err(axum::http::StatusCode::UNSUPPORTED_MEDIA_TYPE.into_response());
         }
     };
+    let mode: crate::db::bulk::Mode = params.mode.into();
+    let mut bulk: Bulk = Bulk::new(mode);
+
+    let mut data: axum::body::BodyDataStream = body.into_data_stream();
+    let mut buf: tokio_util::bytes::BytesMut = tokio_util::bytes::BytesMut::new();
+    loop {
+        let eof: bool = match data.next().await {
+            Some(Ok(chunk)) => {
+                buf.extend_from_slice(&chunk);
+                false
+            }
+            Some(Err(err)) => {
+                return Err(bulk.reject(body_error_status(&err), format!("Failed to read request body: {err}")));
+            }
+            None => true,
+        };
+        loop {
+            let decoded: Result<Option<crate::web::bulk_body::Item>, String> = match eof {
+                true => decoder.decode_eof(&mut buf),
+                false => decoder.decode(&mut buf),
+            };
+            let item: crate::web::bulk_body::Item = match decoded {
+                Ok(Some(n)) => n,
+                Ok(None) => break,
+                Err(message) => {
+                    return Err(bulk.reject(axum::http::StatusCode::BAD_REQUEST, message));
+                }
+            };
+            bulk.push(item, &principal.owner);
+
+            if mode == crate::db::bulk::Mode::BestEffort && bulk.pending.len() >= crate::db::bulk::BATCH_SIZE {
+                bulk.apply(&mut shared.db_client, &request_id, &principal).await?;
+            }
+        }
+        if eof {
+            break;
+        }
+    }
+
+    /*
+     * In mode `all_or_nothing`, nothing has been applied yet, so the results
+     * known by now are those of invalid operations.
+     */
+    let invalid: bool = bulk.results.iter().flatten().next().is_some();
+    if mode == crate::db::bulk::Mode::AllOrNothing && invalid {
+        for (index, _operation) in bulk.pending.drain(..) {
+            bulk.results[index] = Some(api::BulkItemResult::of(axum::http::StatusCode::FAILED_DEPENDENCY, None));
+        }
+    } else {
+        bulk.apply(&mut shared.db_client, &request_id, &principal).await?;
+    }
+
+    let results: Vec<api::BulkItemResult> = bulk
+        .results
+        .into_iter()
+        .map(|result| {
+            result.unwrap_or_else(|| api::BulkItemResult::of(axum::http::StatusCode::INTERNAL_SERVER_ERROR, None))
+        })
+        .collect();
+    let failed: Vec<axum::http::StatusCode> = results
+        .iter()
+        .filter_map(|result| axum::http::StatusCode::from_u16(result.status).ok())
+        .filter(|status| !status.is_success())
+        .collect();
+    if mode == crate::db::bulk::Mode::AllOrNothing && !failed.is_empty() {
+        let status: axum::http::StatusCode = failed
+            .iter()
+            .copied()
+            .find(|status| *status != axum::http::StatusCode::FAILED_DEPENDENCY)
+            .unwrap_or(axum::http::StatusCode::INTERNAL_SERVER_ERROR);
+        log::error!(
+            "Rolled back all {} bulk operations: Some failed, first with {status}",
+            results.len()
+        );
+        return Ok((status, axum::Json(api::BulkResults { results })));
+    }
+    log::info!(
+        "Applied {} of {} bulk operations",
+        results.len() - failed.len(),
+        results.len()
+    );
+
+    Ok((axum::http::StatusCode::OK, axum::Json(api::BulkResults { results })))
+}
+
+/// Operations of a bulk request as they're parsed and applied, see
+/// [`post_bulk`].
+struct Bulk {
+    mode: crate::db::bulk::Mode,
+    /// Result of each operation, in order, once known. Invalid operations are
+    /// known right away, the others once applied.
+    results: Vec<Option<api::BulkItemResult>>,
+    /// Valid operations not sent to the database yet, along with the index of
+    /// their result.
+    pending: Vec<(usize, crate::db::bulk::Operation)>,
+    /// Operations applied by batches sent to the database so far.
+    applied: usize,
+}
+
+impl Bulk {
+    fn new(mode: crate::db::bulk::Mode) -> Self {
+        Self {
+            mode,
+            results: Vec::new(),
+            pending: Vec::new(),
+            applied: 0,
+        }
+    }
+
+    /// `owner` becomes the owner of created books.
+    fn push(&mut self, item: crate::web::bulk_body::Item, owner: &str) {
+        use crate::web::validation::Validate;
+
+        let operation: Result<api::BulkOperation, String> =
+            item.and_then(|frame| serde_json::from_slice(&frame).map_err(|err| err.to_string()));
+        let result: Option<api::BulkItemResult> = match operation.map(Validate::validate) {
+            Ok(Ok(operation)) => {
+                self.pending.push((self.results.len(), operation.into_operation(owner)));
+                None
+            }
+            Ok(Err(errors)) => Some(api::BulkItemResult::invalid(errors)),
+            Err(err) => Some(api::BulkItemResult::message(axum::http::StatusCode::BAD_REQUEST, err)),
+        };
+        self.results.push(result);
+    }
+
+    /// Sends the pending operations to the database as a single query.
+    async fn apply(
+        &mut self,
+        db_client: &mut crate::web::db_client::DatabaseClient,
+        request_id: &crate::web::middleware::RequestId,
+        principal: &crate::web::auth::Principal,
+    ) -> Result<(), axum::response::Response> {
+        use axum::response::IntoResponse;
 
-    axum::http::StatusCode::NO_CONTENT
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let (indices, operations): (Vec<usize>, Vec<crate::db::bulk::Operation>) =
+            std::mem::take(&mut self.pending).into_iter().unzip();
+        let book_ids: Vec<uuid::Uuid> = operations.iter().map(crate::db::bulk::Operation::book_id).collect();
+
+        let outcomes: Vec<crate::db::bulk::Outcome> = match db_client
+            .bulk_books(request_id, operations, principal.authority(), self.mode)
+            .await
+        {
+            Ok(n) => n,
+            Err(err) => {
+                if self.applied > 0 {
+                    log::error!("Bulk request failed after applying {} operations", self.applied);
+                }
+                return Err(err.into_response());
+            }
+        };
+
+        for ((index, book_id), outcome) in indices.into_iter().zip(book_ids).zip(outcomes) {
+            if matches!(
+                outcome,
+                crate::db::bulk::Outcome::Inserted
+                    | crate::db::bulk::Outcome::Replaced
+                    | crate::db::bulk::Outcome::Removed
+            ) {
+                self.applied += 1;
+            }
+            self.results[index] = Some(api::BulkItemResult::of(outcome.into(), Some(book_id)));
+        }
+
+        Ok(())
+    }
+
+    /// Rejects the request as a whole, telling how many operations were
+    /// applied already in mode `best_effort`.
+    fn reject(&self, status: axum::http::StatusCode, message: String) -> axum::response::Response {
+        use axum::response::IntoResponse;
+
+        let message: String = match self.applied {
+            0 => message,
+            applied => format!("{message} ({applied} operations were applied already)"),
+        };
+        log::error!("{}: {message}", status.canonical_reason().unwrap_or_default());
+        (status, message).into_response()
+    }
+}
+
+/// 413 Payload Too Large if the body exceeded a limit, like for bodies that
+/// are received as a whole, 400 Bad Request otherwise, e.g. for a malformed
+/// compressed body.
+fn body_error_status(err: &axum::Error) -> axum::http::StatusCode {
+    let mut source: Option<&(dyn std::error::Error + 'static)> = Some(err);
+    while let Some(err) = source {
+        if err.is::<http_body_util::LengthLimitError>() {
+            return axum::http::StatusCode::PAYLOAD_TOO_LARGE;
+        }
+        source = err.source();
+    }
+    axum::http::StatusCode::BAD_REQUEST
 }
 
 pub async fn get_all(
     axum::extract::State(mut shared): axum::extract::State<crate::web::Shared>,
-) -> Result<axum::Json<Vec<api::BookPopulated>>, axum::http::StatusCode> {
-    let all_books: Vec<crate::db::schema_v1::Book> = match shared.db_client.select_books_not_removed().await {
+    axum::Extension(request_id): axum::Extension<crate::web::middleware::RequestId>,
+    principal: crate::web::auth::Principal,
+) -> Result<axum::Json<Vec<api::BookPopulated>>, axum::response::Response> {
+    use axum::response::IntoResponse;
+
+    if let Err(status) = principal.authorize(crate::web::auth::Scope::Read) {
+        return Err(status.into_response());
+    }
+
+    let all_books: Vec<crate::db::schema_v1::Book> = match shared.db_client.select_books_not_removed(&request_id).await
+    {
         Ok(n) => n,
-        Err(_) => {
-            return Err(axum::http::StatusCode::INTERNAL_SERVER_ERROR);
+        Err(err) => {
+            return Err(err.into_response());
         }
     };
 
@@ -73,48 +529,99 @@ pub async fn get_all(
 
 pub async fn get_one_by_id(
     axum::extract::State(mut shared): axum::extract::State<crate::web::Shared>,
+    axum::Extension(request_id): axum::Extension<crate::web::middleware::RequestId>,
+    principal: crate::web::auth::Principal,
     axum::extract::Path(book_id): axum::extract::Path<uuid::Uuid>,
-) -> Result<axum::Json<api::BookPopulated>, axum::http::StatusCode> {
-    let book: crate::db::schema_v1::Book = match shared.db_client.select_book_by_id(book_id).await {
-        Ok(n) => n,
-        Err(_) => {
-            return Err(axum::http::StatusCode::INTERNAL_SERVER_ERROR);
+) -> Result<axum::Json<api::BookPopulated>, axum::response::Response> {
+    use axum::response::IntoResponse;
+
+    if let Err(status) = principal.authorize(crate::web::auth::Scope::Read) {
+        return Err(status.into_response());
+    }
+
+    let book: crate::db::schema_v1::Book = match shared.db_client.select_book_by_id(&request_id, book_id).await {
+        Ok(Some(n)) => n,
+        Ok(None) => {
+            log::error!(book_id:% = book_id; "Not found: Cannot GET: No book {book_id}");
+            return Err(axum::http::StatusCode::NOT_FOUND.into_response());
+        }
+        Err(err) => {
+            return Err(err.into_response());
         }
     };
 
     if let Some(removed_at_utc) = book.removed_at_utc {
-        log::error!("Forbidden: Cannot GET: Book {book_id} was removed at {removed_at_utc} UTC");
-        return Err(axum::http::StatusCode::FORBIDDEN);
+        log::error!(book_id:% = book_id; "Forbidden: Cannot GET: Book {book_id} was removed at {removed_at_utc} UTC");
+        return Err(axum::http::StatusCode::FORBIDDEN.into_response());
     }
 
     Ok(axum::Json(book.into()))
 }
 
+/// Remove a book, i.e. UPDATE it as removed. Owners can remove their own books
+/// and admins can remove any book, which is enforced by the database query.
+///
+/// - 204 No Content: Removed successfully.
+///
+/// - 400 Bad Request: Book was removed already.
+///
+/// - 403 Forbidden: Client lacks scope `books:write`, or the book is owned by
+///   someone else.
+///
+/// - 404 Not Found: No such book.
+///
+/// - 503 Service Unavailable: Request was shed, like in [`post_one`].
 pub async fn delete_one_by_id(
     axum::extract::State(mut shared): axum::extract::State<crate::web::Shared>,
+    axum::Extension(request_id): axum::Extension<crate::web::middleware::RequestId>,
+    principal: crate::web::auth::Principal,
     axum::extract::Path(book_id): axum::extract::Path<uuid::Uuid>,
-) -> axum::http::StatusCode {
-    let existing: crate::db::schema_v1::Book = match shared.db_client.select_book_by_id(book_id).await {
-        Ok(n) => n,
-        Err(_) => {
-            return axum::http::StatusCode::INTERNAL_SERVER_ERROR;
-        }
-    };
+) -> axum::response::Response {
+    use axum::response::IntoResponse;
 
-    if let Some(removed_at_utc) = existing.removed_at_utc {
-        log::error!("Bad request: Cannot DELETE: Book {book_id} already removed at {removed_at_utc} UTC");
-        return axum::http::StatusCode::BAD_REQUEST;
+    if let Err(status) = principal.authorize(crate::web::auth::Scope::Write) {
+        return status.into_response();
     }
 
-    let removal_instant: chrono::DateTime<chrono::Utc> = chrono::Utc::now();
-    let _rows_affected: usize = match shared.db_client.update_book_set_removed(book_id, removal_instant).await {
-        Ok(n) => n,
-        Err(_) => {
-            return axum::http::StatusCode::INTERNAL_SERVER_ERROR;
+    let existing: Option<crate::db::schema_v1::Book> =
+        match shared.db_client.select_book_by_id(&request_id, book_id).await {
+            Ok(n) => n,
+            Err(err) => {
+                return err.into_response();
+            }
+        };
+
+    /*
+     * Answered like the same removal in `post_bulk`, see `Outcome`.
+     */
+    let outcome: crate::db::bulk::Outcome = if existing.is_none() {
+        log::error!(book_id:% = book_id; "Not found: Cannot DELETE: No book {book_id}");
+        crate::db::bulk::Outcome::NotFound
+    } else if let Some(removed_at_utc) = existing.and_then(|book| book.removed_at_utc) {
+        log::error!(book_id:% = book_id; "Bad request: Cannot DELETE: Book {book_id} already removed at {removed_at_utc} UTC");
+        crate::db::bulk::Outcome::AlreadyRemoved
+    } else {
+        let removal_instant: chrono::DateTime<chrono::Utc> = chrono::Utc::now();
+        let rows_affected: usize = match shared
+            .db_client
+            .update_book_set_removed(&request_id, book_id, removal_instant, principal.authority())
+            .await
+        {
+            Ok(n) => n,
+            Err(err) => {
+                return err.into_response();
+            }
+        };
+
+        if rows_affected == 0 {
+            log::error!(book_id:% = book_id; "Forbidden: Cannot DELETE: Book {book_id} is not owned by {}", principal.name);
+            crate::db::bulk::Outcome::Refused
+        } else {
+            crate::db::bulk::Outcome::Removed
         }
     };
 
-    axum::http::StatusCode::NO_CONTENT
+    axum::http::StatusCode::from(outcome).into_response()
 }
 
 mod api {
@@ -125,6 +632,9 @@ mod api {
     pub struct BookPopulated {
         /// Metadata.
         pub id: uuid::Uuid,
+        /// Metadata: Identity of the principal that created the book, if known,
+        /// e.g. `key:<name of API key>`.
+        pub owner: Option<String>,
 
         pub title: String,
         pub genre: String,
@@ -142,11 +652,268 @@ mod api {
         pub page_count: u16,
     }
 
+    /// Number of pages a book may have.
+    pub const PAGE_COUNT: std::ops::RangeInclusive<u16> = 1..=10_000;
+
+    /// Checks the fields shared by the input types, pushing any errors.
+    /// Returns the title normalized, see [`crate::web::validation::text`].
+    fn validate_title_and_page_count(
+        title: String,
+        page_count: u16,
+        errors: &mut crate::web::validation::Errors,
+    ) -> String {
+        let title: String = match crate::web::validation::text(&title, 256) {
+            Ok(n) => n,
+            Err(err) => {
+                errors.push("title", err);
+                title
+            }
+        };
+        if !PAGE_COUNT.contains(&page_count) {
+            errors.push(
+                "page_count",
+                format!(
+                    "Must be from {} to {}, was {}",
+                    PAGE_COUNT.start(),
+                    PAGE_COUNT.end(),
+                    page_count
+                ),
+            );
+        }
+
+        title
+    }
+
+    impl crate::web::validation::Validate for BookUnpopulated {
+        fn validate(self) -> Result<Self, crate::web::validation::Errors> {
+            let mut errors: crate::web::validation::Errors = crate::web::validation::Errors::default();
+            let title: String = validate_title_and_page_count(self.title, self.page_count, &mut errors);
+
+            errors.check(Self {
+                title,
+                page_count: self.page_count,
+            })
+        }
+    }
+
+    /// HTTP API schema of a book to create or replace with a known ID, which is
+    /// why the genre is included unlike in [`BookUnpopulated`].
+    #[derive(serde::Deserialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct BookReplacement {
+        pub title: String,
+        pub genre: Genre,
+        pub page_count: u16,
+    }
+
+    impl crate::web::validation::Validate for BookReplacement {
+        fn validate(self) -> Result<Self, crate::web::validation::Errors> {
+            let mut errors: crate::web::validation::Errors = crate::web::validation::Errors::default();
+            let title: String = validate_title_and_page_count(self.title, self.page_count, &mut errors);
+
+            errors.check(Self {
+                title,
+                genre: self.genre,
+                page_count: self.page_count,
+            })
+        }
+    }
+
+    /// One operation of a bulk request, e.g.:
+    ///
+    /// ```json
+    /// { "op": "create", "genre": "horror", "title": "Foo Bar!", "page_count": 123 }
+    /// { "op": "put", "id": "0b6e2a5e-...", "genre": "horror", "title": "Foo Bar!", "page_count": 123 }
+    /// { "op": "delete", "id": "0b6e2a5e-..." }
+    /// ```
+    #[derive(serde::Deserialize)]
+    #[serde(tag = "op", rename_all = "snake_case", deny_unknown_fields)]
+    pub enum BulkOperation {
+        /// Like [`super::post_one`].
+        Create {
+            genre: Genre,
+            title: String,
+            page_count: u16,
+        },
+        /// Like [`super::put_one_by_id`].
+        Put {
+            id: uuid::Uuid,
+            genre: Genre,
+            title: String,
+            page_count: u16,
+        },
+        /// Like [`super::delete_one_by_id`].
+        Delete { id: uuid::Uuid },
+    }
+
+    impl crate::web::validation::Validate for BulkOperation {
+        fn validate(self) -> Result<Self, crate::web::validation::Errors> {
+            let mut errors: crate::web::validation::Errors = crate::web::validation::Errors::default();
+            let operation: BulkOperation = match self {
+                BulkOperation::Create {
+                    genre,
+                    title,
+                    page_count,
+                } => BulkOperation::Create {
+                    genre,
+                    title: validate_title_and_page_count(title, page_count, &mut errors),
+                    page_count,
+                },
+                BulkOperation::Put {
+                    id,
+                    genre,
+                    title,
+                    page_count,
+                } => BulkOperation::Put {
+                    id,
+                    genre,
+                    title: validate_title_and_page_count(title, page_count, &mut errors),
+                    page_count,
+                },
+                BulkOperation::Delete { id } => BulkOperation::Delete { id },
+            };
+
+            errors.check(operation)
+        }
+    }
+
+    impl BulkOperation {
+        /// `owner` only applies to created books, like in
+        /// [`BookReplacement::populate`].
+        pub fn into_operation(self, owner: &str) -> crate::db::bulk::Operation {
+            match self {
+                BulkOperation::Create {
+                    genre,
+                    title,
+                    page_count,
+                } => crate::db::bulk::Operation::Insert(BookUnpopulated { title, page_count }.populate(
+                    uuid::Uuid::now_v7(),
+                    genre,
+                    owner.to_owned(),
+                )),
+                BulkOperation::Put {
+                    id,
+                    genre,
+                    title,
+                    page_count,
+                } => crate::db::bulk::Operation::Upsert(
+                    BookReplacement {
+                        title,
+                        genre,
+                        page_count,
+                    }
+                    .populate(id, owner.to_owned()),
+                ),
+                BulkOperation::Delete { id } => crate::db::bulk::Operation::Remove(id),
+            }
+        }
+    }
+
+    #[derive(serde::Deserialize, Default)]
+    #[serde(rename_all = "snake_case")]
+    pub enum BulkMode {
+        #[default]
+        AllOrNothing,
+        BestEffort,
+    }
+
+    impl From<BulkMode> for crate::db::bulk::Mode {
+        fn from(mode: BulkMode) -> Self {
+            match mode {
+                BulkMode::AllOrNothing => crate::db::bulk::Mode::AllOrNothing,
+                BulkMode::BestEffort => crate::db::bulk::Mode::BestEffort,
+            }
+        }
+    }
+
+    #[derive(serde::Deserialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct BulkParams {
+        #[serde(default)]
+        pub mode: BulkMode,
+    }
+
+    #[derive(serde::Serialize)]
+    pub struct BulkResults {
+        pub results: Vec<BulkItemResult>,
+    }
+
+    /// Result of one operation of a bulk request.
+    #[derive(serde::Serialize)]
+    pub struct BulkItemResult {
+        /// Status of the corresponding single request.
+        pub status: u16,
+        /// ID of the affected book, if the operation was attempted.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub id: Option<uuid::Uuid>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub message: Option<String>,
+        #[serde(flatten)]
+        pub errors: Option<crate::web::validation::Errors>,
+    }
+
+    impl BulkItemResult {
+        pub fn of(status: axum::http::StatusCode, id: Option<uuid::Uuid>) -> Self {
+            Self {
+                status: status.as_u16(),
+                id,
+                message: None,
+                errors: None,
+            }
+        }
+
+        pub fn message(status: axum::http::StatusCode, message: String) -> Self {
+            Self {
+                message: Some(message),
+                ..Self::of(status, None)
+            }
+        }
+
+        pub fn invalid(errors: crate::web::validation::Errors) -> Self {
+            Self {
+                errors: Some(errors),
+                ..Self::of(axum::http::StatusCode::UNPROCESSABLE_ENTITY, None)
+            }
+        }
+    }
+
+    impl From<crate::db::bulk::Outcome> for axum::http::StatusCode {
+        fn from(outcome: crate::db::bulk::Outcome) -> Self {
+            match outcome {
+                crate::db::bulk::Outcome::Inserted => axum::http::StatusCode::CREATED,
+                crate::db::bulk::Outcome::Replaced => axum::http::StatusCode::OK,
+                crate::db::bulk::Outcome::Removed => axum::http::StatusCode::NO_CONTENT,
+                crate::db::bulk::Outcome::Refused => axum::http::StatusCode::FORBIDDEN,
+                crate::db::bulk::Outcome::NotFound => axum::http::StatusCode::NOT_FOUND,
+                crate::db::bulk::Outcome::AlreadyRemoved => axum::http::StatusCode::BAD_REQUEST,
+                crate::db::bulk::Outcome::Failed => axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+                crate::db::bulk::Outcome::RolledBack => axum::http::StatusCode::FAILED_DEPENDENCY,
+            }
+        }
+    }
+
+    impl BookReplacement {
+        /// `owner` only applies if the book is created, as replacing a book
+        /// keeps its owner.
+        pub fn populate(self, id: uuid::Uuid, owner: String) -> crate::db::schema_v1::Book {
+            crate::db::schema_v1::Book {
+                id,
+                removed_at_utc: None,
+                owner: Some(owner),
+
+                title: self.title,
+                genre: self.genre.to_string(),
+                page_count: self.page_count.into(),
+            }
+        }
+    }
+
     impl BookUnpopulated {
-        pub fn populate(self, id: uuid::Uuid, genre: Genre) -> crate::db::schema_v1::Book {
+        pub fn populate(self, id: uuid::Uuid, genre: Genre, owner: String) -> crate::db::schema_v1::Book {
             crate::db::schema_v1::Book {
                 id,
                 removed_at_utc: None,
+                owner: Some(owner),
 
                 title: self.title,
                 genre: genre.to_string(),
@@ -159,6 +926,7 @@ mod api {
         fn from(db: crate::db::schema_v1::Book) -> Self {
             Self {
                 id: db.id,
+                owner: db.owner,
                 title: db.title,
                 genre: db.genre.to_string(),
                 page_count: db.page_count.try_into().ok(),
@@ -200,3 +968,303 @@ mod api {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn bounds_page_count() {
+        use crate::web::validation::Validate;
+
+        for (page_count, valid) in [(0, false), (1, true), (10_000, true), (10_001, false)] {
+            let book: super::api::BookUnpopulated = super::api::BookUnpopulated {
+                title: "Title".to_owned(),
+                page_count,
+            };
+
+            assert_eq!(book.validate().is_ok(), valid, "{page_count}");
+        }
+    }
+
+    #[tokio::test]
+    async fn lists_errors_of_each_field() {
+        let db_mailbox: crate::db::Mailbox = crate::db::Mailbox::open(1);
+        let address: std::net::SocketAddr =
+            crate::web::testing::serve(&crate::web::testing::config(), &db_mailbox).await;
+
+        let response: crate::web::testing::Response = crate::web::testing::send(
+            address,
+            "POST",
+            "/api/books/v1/genre/history",
+            &[
+                &format!("authorization: Bearer {}", crate::web::testing::ADMIN_TOKEN),
+                "content-type: application/json",
+            ],
+            r#"{"title":"a\u0007b","page_count":0}"#,
+        )
+        .await;
+
+        assert_eq!(response.status, 422);
+        assert_eq!(
+            response.json(),
+            serde_json::json!({
+                "errors": [
+                    { "field": "title", "message": "Must not contain control characters, found '\\u{7}'" },
+                    { "field": "page_count", "message": "Must be from 1 to 10000, was 0" },
+                ]
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_body_over_limit() {
+        let mut config: crate::web::Config = crate::web::testing::config();
+        config.request_body_limit = crate::web::RequestBodyLimit(64);
+        let db_mailbox: crate::db::Mailbox = crate::db::Mailbox::open(1);
+        let address: std::net::SocketAddr = crate::web::testing::serve(&config, &db_mailbox).await;
+        let headers: [&str; 2] = [
+            &format!("authorization: Bearer {}", crate::web::testing::ADMIN_TOKEN),
+            "content-type: application/json",
+        ];
+
+        let body: String = serde_json::json!({ "title": "x".repeat(64), "page_count": 1 }).to_string();
+        let response: crate::web::testing::Response =
+            crate::web::testing::send(address, "POST", "/api/books/v1/genre/history", &headers, &body).await;
+
+        assert_eq!(response.status, 413);
+    }
+
+    /// Sends the request with the API key, and a JSON body unless it's empty.
+    async fn send(
+        address: std::net::SocketAddr,
+        api_key: &str,
+        method: &str,
+        path: &str,
+        body: &str,
+    ) -> crate::web::testing::Response {
+        let authorization: String = format!("authorization: Bearer {api_key}");
+        let mut headers: Vec<&str> = vec![&authorization];
+        if !body.is_empty() {
+            headers.push("content-type: application/json");
+        }
+        crate::web::testing::send(address, method, path, &headers, body).await
+    }
+
+    /// Issues an API key of a name of its own, allowed to read and write.
+    async fn issue_api_key(address: std::net::SocketAddr) -> String {
+        let name: String = format!("books-{}", uuid::Uuid::new_v4());
+        let (_id, key) = crate::web::testing::issue_api_key(address, &name, &["books:read", "books:write"]).await;
+        key
+    }
+
+    const REPLACEMENT: &str = r#"{"title":"Title","genre":"history","page_count":1}"#;
+
+    /// Requires PostgreSQL, see [`crate::web::testing::connect_db`].
+    #[tokio::test]
+    #[ignore]
+    async fn creates_then_replaces_book_by_id() {
+        let db_mailbox: crate::db::Mailbox = crate::web::testing::connect_db();
+        let address: std::net::SocketAddr =
+            crate::web::testing::serve(&crate::web::testing::config(), &db_mailbox).await;
+        let api_key: String = issue_api_key(address).await;
+        let path: String = format!("/api/books/v1/{}", uuid::Uuid::new_v4());
+
+        let response: crate::web::testing::Response = send(address, &api_key, "PUT", &path, REPLACEMENT).await;
+        assert_eq!(response.status, 201);
+        assert_eq!(response.json()["title"], "Title");
+
+        let replacement: &str = r#"{"title":"Other title","genre":"horror","page_count":2}"#;
+        let response: crate::web::testing::Response = send(address, &api_key, "PUT", &path, replacement).await;
+        assert_eq!(response.status, 200);
+        assert_eq!(response.json()["title"], "Other title");
+        assert_eq!(response.json()["genre"], "Horror");
+    }
+
+    /// Requires PostgreSQL, see [`crate::web::testing::connect_db`].
+    #[tokio::test]
+    #[ignore]
+    async fn forbids_replacing_removed_or_foreign_book() {
+        let db_mailbox: crate::db::Mailbox = crate::web::testing::connect_db();
+        let address: std::net::SocketAddr =
+            crate::web::testing::serve(&crate::web::testing::config(), &db_mailbox).await;
+        let (owner, other) = (issue_api_key(address).await, issue_api_key(address).await);
+        let path: String = format!("/api/books/v1/{}", uuid::Uuid::new_v4());
+
+        let response: crate::web::testing::Response = send(address, &owner, "PUT", &path, REPLACEMENT).await;
+        assert_eq!(response.status, 201);
+        let response: crate::web::testing::Response = send(address, &other, "PUT", &path, REPLACEMENT).await;
+        assert_eq!(response.status, 403);
+
+        let response: crate::web::testing::Response = send(address, &owner, "DELETE", &path, "").await;
+        assert_eq!(response.status, 204);
+        let response: crate::web::testing::Response = send(address, &owner, "PUT", &path, REPLACEMENT).await;
+        assert_eq!(response.status, 403);
+    }
+
+    /// Requires PostgreSQL, see [`crate::web::testing::connect_db`].
+    #[tokio::test]
+    #[ignore]
+    async fn creates_book_with_uuid_v7() {
+        let db_mailbox: crate::db::Mailbox = crate::web::testing::connect_db();
+        let address: std::net::SocketAddr =
+            crate::web::testing::serve(&crate::web::testing::config(), &db_mailbox).await;
+        let api_key: String = issue_api_key(address).await;
+        let title: String = uuid::Uuid::new_v4().to_string();
+
+        let body: String = serde_json::json!({ "title": title, "page_count": 1 }).to_string();
+        let response: crate::web::testing::Response =
+            send(address, &api_key, "POST", "/api/books/v1/genre/history", &body).await;
+        assert_eq!(response.status, 204);
+
+        let response: crate::web::testing::Response = send(address, &api_key, "GET", "/api/books/v1", "").await;
+        let books: serde_json::Value = response.json();
+        let created: &serde_json::Value = match books.as_array().unwrap().iter().find(|book| book["title"] == title) {
+            Some(n) => n,
+            None => panic!("Book was not created"),
+        };
+        let id: uuid::Uuid = created["id"].as_str().unwrap().parse().unwrap();
+        assert_eq!(id.get_version(), Some(uuid::Version::SortRand));
+    }
+
+    /// Removals in [`super::post_bulk`] are answered like the same request to
+    /// [`super::delete_one_by_id`], as documented there. Requires PostgreSQL,
+    /// see [`crate::web::testing::connect_db`].
+    #[tokio::test]
+    #[ignore]
+    async fn bulk_removals_agree_with_delete_one_by_id() {
+        let db_mailbox: crate::db::Mailbox = crate::web::testing::connect_db();
+        let address: std::net::SocketAddr =
+            crate::web::testing::serve(&crate::web::testing::config(), &db_mailbox).await;
+        let (owner, other) = (issue_api_key(address).await, issue_api_key(address).await);
+
+        /*
+         * A book of its own for each path, as removing it changes its state.
+         */
+        let book_in_state = async |state: &str| -> String {
+            let path: String = format!("/api/books/v1/{}", uuid::Uuid::new_v4());
+            let creator: &str = if state == "not owned" { &other } else { &owner };
+            if state != "missing" {
+                assert_eq!(send(address, creator, "PUT", &path, REPLACEMENT).await.status, 201);
+            }
+            if state == "removed" {
+                assert_eq!(send(address, creator, "DELETE", &path, "").await.status, 204);
+            }
+            path
+        };
+
+        for (state, status) in [("missing", 404), ("removed", 400), ("not owned", 403), ("owned", 204)] {
+            let path: String = book_in_state(state).await;
+            let single: crate::web::testing::Response = send(address, &owner, "DELETE", &path, "").await;
+
+            let path: String = book_in_state(state).await;
+            let id: &str = path.rsplit('/').next().unwrap();
+            let body: String = serde_json::json!([{ "op": "delete", "id": id }]).to_string();
+            let bulk: crate::web::testing::Response =
+                send(address, &owner, "POST", "/api/books/v1/bulk?mode=best_effort", &body).await;
+            assert_eq!(bulk.status, 200, "{}", bulk.body);
+
+            assert_eq!(single.status, status, "{state}");
+            assert_eq!(bulk.json()["results"][0]["status"], status, "{state}");
+        }
+    }
+
+    /// Operation of [`super::post_bulk`] creating or replacing the book.
+    fn put_operation(id: uuid::Uuid, title: &str) -> serde_json::Value {
+        serde_json::json!({ "op": "put", "id": id, "genre": "history", "title": title, "page_count": 1 })
+    }
+
+    /// Statuses of the results of the operations, in order.
+    fn result_statuses(response: &crate::web::testing::Response) -> Vec<u64> {
+        response.json()["results"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|result| result["status"].as_u64().unwrap())
+            .collect()
+    }
+
+    /// Requires PostgreSQL, see [`crate::web::testing::connect_db`].
+    #[tokio::test]
+    #[ignore]
+    async fn rolls_back_all_bulk_operations_if_one_fails() {
+        let db_mailbox: crate::db::Mailbox = crate::web::testing::connect_db();
+        let address: std::net::SocketAddr =
+            crate::web::testing::serve(&crate::web::testing::config(), &db_mailbox).await;
+        let (owner, other) = (issue_api_key(address).await, issue_api_key(address).await);
+        let (created, foreign) = (uuid::Uuid::new_v4(), uuid::Uuid::new_v4());
+        let path: String = format!("/api/books/v1/{foreign}");
+        assert_eq!(send(address, &other, "PUT", &path, REPLACEMENT).await.status, 201);
+
+        let body: String = serde_json::json!([
+            put_operation(created, "Title"),
+            put_operation(foreign, "Title"),
+            { "op": "create", "genre": "history", "title": "Title", "page_count": 1 },
+        ])
+        .to_string();
+        let response: crate::web::testing::Response = send(address, &owner, "POST", "/api/books/v1/bulk", &body).await;
+
+        assert_eq!(response.status, 403, "{}", response.body);
+        assert_eq!(result_statuses(&response), [424, 403, 424]);
+        let path: String = format!("/api/books/v1/{created}");
+        assert_eq!(send(address, &owner, "GET", &path, "").await.status, 404);
+    }
+
+    /// Requires PostgreSQL, see [`crate::web::testing::connect_db`].
+    #[tokio::test]
+    #[ignore]
+    async fn applies_bulk_operations_on_best_effort() {
+        let db_mailbox: crate::db::Mailbox = crate::web::testing::connect_db();
+        let address: std::net::SocketAddr =
+            crate::web::testing::serve(&crate::web::testing::config(), &db_mailbox).await;
+        let (owner, other) = (issue_api_key(address).await, issue_api_key(address).await);
+        let (created, foreign, replaced) = (uuid::Uuid::new_v4(), uuid::Uuid::new_v4(), uuid::Uuid::new_v4());
+        for (api_key, id) in [(&other, foreign), (&owner, replaced)] {
+            let path: String = format!("/api/books/v1/{id}");
+            assert_eq!(send(address, api_key, "PUT", &path, REPLACEMENT).await.status, 201);
+        }
+
+        let body: String = serde_json::json!([
+            put_operation(created, "Title"),
+            put_operation(foreign, "Title"),
+            { "op": "delete", "id": uuid::Uuid::new_v4() },
+            put_operation(replaced, "Other title"),
+            { "op": "create", "genre": "history", "title": "", "page_count": 1 },
+        ])
+        .to_string();
+        let response: crate::web::testing::Response =
+            send(address, &owner, "POST", "/api/books/v1/bulk?mode=best_effort", &body).await;
+
+        assert_eq!(response.status, 200, "{}", response.body);
+        assert_eq!(result_statuses(&response), [201, 403, 404, 200, 422]);
+        let path: String = format!("/api/books/v1/{replaced}");
+        assert_eq!(
+            send(address, &owner, "GET", &path, "").await.json()["title"],
+            "Other title"
+        );
+    }
+
+    /// Requires PostgreSQL, see [`crate::web::testing::connect_db`].
+    #[tokio::test]
+    #[ignore]
+    async fn fails_only_oversized_bulk_operation() {
+        let db_mailbox: crate::db::Mailbox = crate::web::testing::connect_db();
+        let address: std::net::SocketAddr =
+            crate::web::testing::serve(&crate::web::testing::config(), &db_mailbox).await;
+        let api_key: String = issue_api_key(address).await;
+
+        let body: String = serde_json::json!([
+            put_operation(uuid::Uuid::new_v4(), "Title"),
+            put_operation(uuid::Uuid::new_v4(), &"a".repeat(crate::web::bulk_body::ITEM_MAX_BYTES)),
+            put_operation(uuid::Uuid::new_v4(), "Title"),
+        ])
+        .to_string();
+        let response: crate::web::testing::Response =
+            send(address, &api_key, "POST", "/api/books/v1/bulk?mode=best_effort", &body).await;
+
+        assert_eq!(response.status, 200, "{}", response.body);
+        assert_eq!(result_statuses(&response), [201, 400, 201]);
+        assert_eq!(
+            response.json()["results"][1]["message"],
+            format!("Operation exceeds {} bytes", crate::web::bulk_body::ITEM_MAX_BYTES)
+        );
+    }
+}
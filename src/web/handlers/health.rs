@@ -0,0 +1,43 @@
+//! Health probes. Liveness tells whether the process is up at all, readiness
+//! tells whether it should be sent traffic.
+
+/// How long the readiness probe waits for its round trip through the database
+/// actor before considering the database unavailable.
+const READINESS_DB_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// - 204 No Content: The process is alive.
+pub async fn get_liveness() -> axum::http::StatusCode {
+    axum::http::StatusCode::NO_CONTENT
+}
+
+/// - 204 No Content: Ready to serve traffic.
+///
+/// - 503 Service Unavailable:
+///
+///   - Global shutdown has been initiated, i.e. traffic should be drained.
+///
+///   - Database actor didn't complete a `SELECT 1` round trip in time, e.g.
+///     because it's disconnected or being restarted.
+///
+///   - Database has migrations that have not been applied yet.
+pub async fn get_readiness(
+    axum::extract::State(mut shared): axum::extract::State<crate::web::Shared>,
+    axum::Extension(request_id): axum::Extension<crate::web::middleware::RequestId>,
+) -> axum::http::StatusCode {
+    if shared.term_token.is_cancelled() {
+        log::warn!("Not ready: Shutting down");
+        return axum::http::StatusCode::SERVICE_UNAVAILABLE;
+    }
+
+    match tokio::time::timeout(READINESS_DB_TIMEOUT, shared.db_client.ping(&request_id)).await {
+        Ok(Ok(())) => axum::http::StatusCode::NO_CONTENT,
+        Ok(Err(_)) => {
+            log::warn!("Not ready: Database unavailable");
+            axum::http::StatusCode::SERVICE_UNAVAILABLE
+        }
+        Err(_elapsed) => {
+            log::warn!("Not ready: Database didn't respond within {READINESS_DB_TIMEOUT:?}");
+            axum::http::StatusCode::SERVICE_UNAVAILABLE
+        }
+    }
+}
@@ -0,0 +1,18 @@
+/// - 200 OK: Metrics in the Prometheus text exposition format.
+///
+/// - 500 Internal Server Error: Encoding the metrics failed.
+pub async fn get_all(
+    axum::extract::State(shared): axum::extract::State<crate::web::Shared>,
+) -> Result<([(axum::http::header::HeaderName, &'static str); 1], String), axum::http::StatusCode> {
+    crate::metrics::METRICS
+        .db_queue_depth
+        .set(shared.db_client.queue_depth() as i64);
+
+    match crate::metrics::METRICS.encode() {
+        Ok(n) => Ok(([(axum::http::header::CONTENT_TYPE, prometheus::TEXT_FORMAT)], n)),
+        Err(err) => {
+            log::error!("{err}");
+            Err(axum::http::StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
@@ -1 +1,4 @@
+pub mod admin;
 pub mod books_v1;
+pub mod health;
+pub mod metrics;
@@ -0,0 +1,279 @@
+//! Header `Idempotency-Key`, with which clients can safely retry requests that
+//! are not idempotent by themselves, e.g. after a timeout: The first response
+//! for each key of each principal is stored in the database, and replayed to
+//! retries of the same request until it expires. See
+//! [`crate::db::Query::InsertBookIdempotently`].
+
+pub const HEADER: axum::http::HeaderName = axum::http::HeaderName::from_static("idempotency-key");
+
+/// Set on replayed responses, so that clients can tell them apart.
+pub const HEADER_REPLAYED: axum::http::HeaderName = axum::http::HeaderName::from_static("idempotent-replayed");
+
+/// How long the first response to a request is kept for replaying, i.e. how
+/// long after it clients may retry the request.
+#[derive(Clone, Copy)]
+pub struct Ttl(pub chrono::TimeDelta);
+
+impl Ttl {
+    /// Reads environment variable `IDEMPOTENCY_KEY_TTL_SECS`. Defaults to 86400
+    /// (24 hours).
+    pub fn from_env() -> Result<Self, String> {
+        let secs: u32 = crate::env::positive_or("IDEMPOTENCY_KEY_TTL_SECS", 24 * 60 * 60)?;
+        Ok(Self(chrono::TimeDelta::seconds(i64::from(secs))))
+    }
+}
+
+/// Value of the header, if any. Must be 1 to 256 visible ASCII characters,
+/// e.g. a UUID.
+pub fn key(headers: &axum::http::HeaderMap) -> Result<Option<String>, String> {
+    let Some(value) = headers.get(HEADER) else {
+        return Ok(None);
+    };
+    let bytes: &[u8] = value.as_bytes();
+    if bytes.is_empty() || bytes.len() > 256 || !bytes.iter().all(u8::is_ascii_graphic) {
+        return Err(format!("{HEADER} must be 1 to 256 visible ASCII characters"));
+    }
+
+    Ok(Some(String::from_utf8_lossy(bytes).into_owned()))
+}
+
+/// SHA-256 of the parts of a request that make it the same request, e.g. the
+/// path parameters and the payload. Each part is prefixed with its length, so
+/// that moving bytes from one part to another changes the hash.
+pub fn request_hash(parts: &[&[u8]]) -> Vec<u8> {
+    use sha2::Digest;
+
+    let mut hasher: sha2::Sha256 = sha2::Sha256::new();
+    for part in parts {
+        hasher.update((part.len() as u64).to_be_bytes());
+        hasher.update(part);
+    }
+
+    hasher.finalize().to_vec()
+}
+
+/// Response as stored for the first request with the key.
+pub fn replay(stored: crate::db::schema_v1::IdempotencyKey) -> axum::response::Response {
+    use axum::response::IntoResponse;
+
+    let status: axum::http::StatusCode = match u16::try_from(stored.response_status)
+        .ok()
+        .and_then(|status| axum::http::StatusCode::from_u16(status).ok())
+    {
+        Some(n) => n,
+        None => {
+            log::error!("Stored response to {HEADER} {:?} has invalid status", stored.key);
+            return axum::http::StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    log::info!("Replayed response to {HEADER} {:?}", stored.key);
+
+    (status, [(HEADER_REPLAYED, "true")], stored.response_body).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    /// Creates a book of genre history with the key, as in
+    /// [`crate::web::handlers::books_v1::post_one`].
+    async fn post(
+        address: std::net::SocketAddr,
+        api_key: &str,
+        key: &str,
+        title: &str,
+    ) -> crate::web::testing::Response {
+        let body: String = serde_json::json!({ "title": title, "page_count": 1 }).to_string();
+        crate::web::testing::send(
+            address,
+            "POST",
+            "/api/books/v1/genre/history",
+            &[
+                &format!("authorization: Bearer {api_key}"),
+                "content-type: application/json",
+                &format!("idempotency-key: {key}"),
+            ],
+            &body,
+        )
+        .await
+    }
+
+    fn connect() -> diesel::PgConnection {
+        use diesel::Connection;
+
+        diesel::PgConnection::establish(&std::env::var("DB_TEST_URI").unwrap()).unwrap()
+    }
+
+    fn count_books_of(owner: &str) -> i64 {
+        use diesel::ExpressionMethods;
+        use diesel::QueryDsl;
+        use diesel::RunQueryDsl;
+
+        crate::db::schema_v1::books::table
+            .filter(crate::db::schema_v1::books::owner.eq(owner))
+            .count()
+            .get_result(&mut connect())
+            .unwrap()
+    }
+
+    /// Issues an API key of a name of its own, returning it along with the
+    /// owner of its books.
+    async fn issue_api_key(address: std::net::SocketAddr) -> (String, String) {
+        let name: String = format!("idempotent-{}", uuid::Uuid::new_v4());
+        let (_id, key) = crate::web::testing::issue_api_key(address, &name, &["books:write"]).await;
+        (key, format!("key:{name}"))
+    }
+
+    /// Requires PostgreSQL, see [`crate::web::testing::connect_db`].
+    #[tokio::test]
+    #[ignore]
+    async fn replays_response_to_same_request() {
+        let db_mailbox: crate::db::Mailbox = crate::web::testing::connect_db();
+        let address: std::net::SocketAddr =
+            crate::web::testing::serve(&crate::web::testing::config(), &db_mailbox).await;
+        let (api_key, owner) = issue_api_key(address).await;
+
+        let response: crate::web::testing::Response = post(address, &api_key, "k1", "Title").await;
+        assert_eq!(response.status, 204);
+        assert_eq!(response.header("idempotent-replayed"), None);
+
+        let response: crate::web::testing::Response = post(address, &api_key, "k1", "Title").await;
+        assert_eq!(response.status, 204);
+        assert_eq!(response.header("idempotent-replayed"), Some("true"));
+        assert_eq!(response.body, "");
+        assert_eq!(count_books_of(&owner), 1);
+    }
+
+    /// Requires PostgreSQL, see [`crate::web::testing::connect_db`].
+    #[tokio::test]
+    #[ignore]
+    async fn rejects_key_reused_for_different_request() {
+        let db_mailbox: crate::db::Mailbox = crate::web::testing::connect_db();
+        let address: std::net::SocketAddr =
+            crate::web::testing::serve(&crate::web::testing::config(), &db_mailbox).await;
+        let (api_key, owner) = issue_api_key(address).await;
+
+        let response: crate::web::testing::Response = post(address, &api_key, "k1", "Title").await;
+        assert_eq!(response.status, 204);
+
+        let response: crate::web::testing::Response = post(address, &api_key, "k1", "Other title").await;
+        assert_eq!(response.status, 422);
+        assert_eq!(
+            response.json(),
+            serde_json::json!({
+                "errors": [{ "field": "Idempotency-Key", "message": "Already used for a different request" }]
+            })
+        );
+        assert_eq!(count_books_of(&owner), 1);
+    }
+
+    /// Requires PostgreSQL, see [`crate::web::testing::connect_db`].
+    #[tokio::test]
+    #[ignore]
+    async fn scopes_keys_per_principal() {
+        let db_mailbox: crate::db::Mailbox = crate::web::testing::connect_db();
+        let address: std::net::SocketAddr =
+            crate::web::testing::serve(&crate::web::testing::config(), &db_mailbox).await;
+
+        for (api_key, owner) in [issue_api_key(address).await, issue_api_key(address).await] {
+            let response: crate::web::testing::Response = post(address, &api_key, "k1", "Title").await;
+            assert_eq!(response.status, 204);
+            assert_eq!(response.header("idempotent-replayed"), None);
+            assert_eq!(count_books_of(&owner), 1);
+        }
+    }
+
+    /// Requires PostgreSQL, see [`crate::web::testing::connect_db`].
+    #[tokio::test]
+    #[ignore]
+    async fn forgets_key_after_ttl() {
+        let mut config: crate::web::Config = crate::web::testing::config();
+        config.idempotency_key_ttl = super::Ttl(chrono::TimeDelta::milliseconds(100));
+        let db_mailbox: crate::db::Mailbox = crate::web::testing::connect_db();
+        let address: std::net::SocketAddr = crate::web::testing::serve(&config, &db_mailbox).await;
+        let (api_key, owner) = issue_api_key(address).await;
+
+        let response: crate::web::testing::Response = post(address, &api_key, "k1", "Title").await;
+        assert_eq!(response.status, 204);
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+        let response: crate::web::testing::Response = post(address, &api_key, "k1", "Other title").await;
+        assert_eq!(response.status, 204);
+        assert_eq!(response.header("idempotent-replayed"), None);
+        assert_eq!(count_books_of(&owner), 2);
+    }
+
+    /// Requires PostgreSQL, see [`crate::web::testing::connect_db`].
+    #[tokio::test]
+    #[ignore]
+    async fn inserts_book_and_key_together() {
+        use diesel::ExpressionMethods;
+        use diesel::QueryDsl;
+        use diesel::RunQueryDsl;
+
+        let db_mailbox: crate::db::Mailbox = crate::web::testing::connect_db();
+        let principal: String = format!("key:idempotent-{}", uuid::Uuid::new_v4());
+        let book = |id: uuid::Uuid| crate::db::schema_v1::Book {
+            id,
+            removed_at_utc: None,
+            owner: Some(principal.clone()),
+            title: "Title".to_owned(),
+            genre: "History".to_owned(),
+            page_count: 1,
+        };
+        let idempotency_key = |key: String| crate::db::schema_v1::IdempotencyKey {
+            principal: principal.clone(),
+            key,
+            created_at_utc: chrono::Utc::now().naive_utc(),
+            expires_at_utc: (chrono::Utc::now() + chrono::TimeDelta::days(1)).naive_utc(),
+            request_hash: vec![0; 32],
+            response_status: 204,
+            response_body: Vec::new(),
+        };
+        let insert = async |book: crate::db::schema_v1::Book, idempotency_key: crate::db::schema_v1::IdempotencyKey| {
+            let (respond_to, response) = tokio::sync::oneshot::channel();
+            let db_query: crate::db::Query = crate::db::Query::InsertBookIdempotently {
+                respond_to,
+                book,
+                idempotency_key,
+            };
+            db_mailbox
+                .get_handle()
+                .send(crate::db::Envelope::new(db_query, None, None))
+                .await
+                .ok()
+                .unwrap();
+            response.await.unwrap()
+        };
+        let keys_of_principal = || -> i64 {
+            crate::db::schema_v1::idempotency_keys::table
+                .filter(crate::db::schema_v1::idempotency_keys::principal.eq(&principal))
+                .count()
+                .get_result(&mut connect())
+                .unwrap()
+        };
+
+        let id: uuid::Uuid = uuid::Uuid::now_v7();
+        assert!(matches!(
+            insert(book(id), idempotency_key("k1".to_owned())).await,
+            Ok(crate::db::Idempotent::Inserted)
+        ));
+
+        /*
+         * Inserting the book fails, as the ID is taken, so the key must not be
+         * stored.
+         */
+        assert!(insert(book(id), idempotency_key("k2".to_owned())).await.is_err());
+        assert_eq!(keys_of_principal(), 1);
+
+        /*
+         * Storing the key fails, as it's too long, so the book must not be
+         * inserted.
+         */
+        assert!(
+            insert(book(uuid::Uuid::now_v7()), idempotency_key("k".repeat(257)))
+                .await
+                .is_err()
+        );
+        assert_eq!(count_books_of(&principal), 1);
+        assert_eq!(keys_of_principal(), 1);
+    }
+}
@@ -0,0 +1,339 @@
+//! Validation of JSON Web Tokens (JWT) issued by an external identity provider,
+//! against the public keys of a JSON Web Key Set (JWKS) file on disk. The file
+//! is reloaded when it changes, so that keys can be rotated without a restart.
+
+/// Asymmetric algorithms only, so that a public key can never be misused as a
+/// shared secret.
+const ALGORITHMS: [jsonwebtoken::Algorithm; 3] = [
+    jsonwebtoken::Algorithm::RS256,
+    jsonwebtoken::Algorithm::ES256,
+    jsonwebtoken::Algorithm::EdDSA,
+];
+
+pub struct Validator {
+    jwks: crate::reload::Watched<jsonwebtoken::jwk::JwkSet>,
+    issuer: String,
+    audience: String,
+    /// Claim listing the scopes granted, either as a space separated string
+    /// (like OAuth 2.0 claim `scope`) or as an array of strings.
+    scopes_claim: String,
+}
+
+impl Validator {
+    /// Reads the following environment variables:
+    ///
+    /// - `JWT_JWKS_FILE`: Path of the JWKS file. Unset by default, i.e. JWTs
+    ///   are not accepted.
+    /// - `JWT_ISSUER`: Required value of claim `iss`. Required if
+    ///   `JWT_JWKS_FILE` is set.
+    /// - `JWT_AUDIENCE`: Required value of claim `aud`. Required if
+    ///   `JWT_JWKS_FILE` is set.
+    /// - `JWT_SCOPES_CLAIM`: Claim listing scopes such as `books:read`.
+    ///   Defaults to `scope`.
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let Some(jwks_file) = std::env::var_os("JWT_JWKS_FILE") else {
+            return Ok(None);
+        };
+        let issuer: String = match std::env::var("JWT_ISSUER") {
+            Ok(n) => n,
+            Err(err) => return Err(format!("JWT_ISSUER is required with JWT_JWKS_FILE: {err}")),
+        };
+        let audience: String = match std::env::var("JWT_AUDIENCE") {
+            Ok(n) => n,
+            Err(err) => return Err(format!("JWT_AUDIENCE is required with JWT_JWKS_FILE: {err}")),
+        };
+        let scopes_claim: String = match std::env::var("JWT_SCOPES_CLAIM") {
+            Ok(n) => n,
+            Err(std::env::VarError::NotPresent) => "scope".to_owned(),
+            Err(err) => return Err(format!("Invalid JWT_SCOPES_CLAIM: {err}")),
+        };
+
+        let jwks: crate::reload::Watched<jsonwebtoken::jwk::JwkSet> =
+            crate::reload::Watched::load(jwks_file.into(), Self::parse_jwks)?;
+
+        Ok(Some(Self {
+            jwks,
+            issuer,
+            audience,
+            scopes_claim,
+        }))
+    }
+
+    fn parse_jwks(contents: &[u8]) -> Result<jsonwebtoken::jwk::JwkSet, String> {
+        serde_json::from_slice(contents).map_err(|err| err.to_string())
+    }
+
+    /// Checks the signature and claims `exp`, `nbf`, `iss` and `aud`, and maps
+    /// the claims to a principal. Scopes not known to this program, e.g.
+    /// `openid`, are ignored.
+    pub fn validate(&self, token: &str) -> Result<crate::web::auth::Principal, String> {
+        let header: jsonwebtoken::Header = jsonwebtoken::decode_header(token).map_err(|err| err.to_string())?;
+        if !ALGORITHMS.contains(&header.alg) {
+            return Err(format!("Algorithm {:?} is not accepted", header.alg));
+        }
+
+        /*
+         * Without a key ID, the key can only be chosen if there's just one.
+         */
+        let jwks: std::sync::Arc<jsonwebtoken::jwk::JwkSet> = self.jwks.get();
+        let jwk: &jsonwebtoken::jwk::Jwk = match &header.kid {
+            Some(kid) => match jwks.find(kid) {
+                Some(n) => n,
+                None => return Err(format!("No key {kid:?} in JWKS")),
+            },
+            None => match jwks.keys.as_slice() {
+                [only] => only,
+                _ => return Err("Token has no key ID and JWKS has multiple keys".to_owned()),
+            },
+        };
+        let key: jsonwebtoken::DecodingKey = jsonwebtoken::DecodingKey::from_jwk(jwk).map_err(|err| err.to_string())?;
+
+        let mut validation: jsonwebtoken::Validation = jsonwebtoken::Validation::new(header.alg);
+        validation.validate_nbf = true;
+        validation.set_issuer(&[&self.issuer]);
+        validation.set_audience(&[&self.audience]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+
+        let claims: serde_json::Map<String, serde_json::Value> =
+            match jsonwebtoken::decode::<serde_json::Map<String, serde_json::Value>>(token, &key, &validation) {
+                Ok(n) => n.claims,
+                Err(err) => return Err(err.to_string()),
+            };
+
+        let subject: String = match claims.get("sub") {
+            Some(serde_json::Value::String(n)) => n.clone(),
+            _ => return Err("Claim sub is not a string".to_owned()),
+        };
+        let granted: Vec<&str> = match claims.get(&self.scopes_claim) {
+            Some(serde_json::Value::String(n)) => n.split_whitespace().collect(),
+            Some(serde_json::Value::Array(n)) => n.iter().filter_map(|scope| scope.as_str()).collect(),
+            _ => Vec::new(),
+        };
+        let scopes: Vec<crate::web::auth::Scope> = granted.iter().filter_map(|scope| scope.parse().ok()).collect();
+
+        Ok(crate::web::auth::Principal {
+            owner: format!("jwt:{}|{subject}", self.issuer),
+            id: subject.clone(),
+            name: subject,
+            scopes,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    const ISSUER: &str = "https://idp.example.com";
+    const AUDIENCE: &str = "books";
+
+    /// RSA keys can't be generated with `ring`, so there's a fixed one.
+    const RSA_PRIVATE_KEY_DER: &[u8] = include_bytes!("testdata/rsa_private_key.der");
+
+    fn base64url(bytes: &[u8]) -> String {
+        use base64::Engine;
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes)
+    }
+
+    /// Signing key, and the public key as JWK with key ID `kid`.
+    fn key(alg: jsonwebtoken::Algorithm, kid: &str) -> (jsonwebtoken::EncodingKey, serde_json::Value) {
+        let rng: ring::rand::SystemRandom = ring::rand::SystemRandom::new();
+        match alg {
+            jsonwebtoken::Algorithm::RS256 => {
+                let key_pair: ring::signature::RsaKeyPair =
+                    ring::signature::RsaKeyPair::from_der(RSA_PRIVATE_KEY_DER).unwrap();
+                let public: ring::rsa::PublicKeyComponents<Vec<u8>> = key_pair.public().into();
+                (
+                    jsonwebtoken::EncodingKey::from_rsa_der(RSA_PRIVATE_KEY_DER),
+                    serde_json::json!({
+                        "kty": "RSA", "kid": kid, "alg": "RS256", "use": "sig",
+                        "n": base64url(&public.n), "e": base64url(&public.e),
+                    }),
+                )
+            }
+            jsonwebtoken::Algorithm::ES256 => {
+                let signing: &ring::signature::EcdsaSigningAlgorithm =
+                    &ring::signature::ECDSA_P256_SHA256_FIXED_SIGNING;
+                let pkcs8: ring::pkcs8::Document =
+                    ring::signature::EcdsaKeyPair::generate_pkcs8(signing, &rng).unwrap();
+                let key_pair: ring::signature::EcdsaKeyPair =
+                    ring::signature::EcdsaKeyPair::from_pkcs8(signing, pkcs8.as_ref(), &rng).unwrap();
+                use ring::signature::KeyPair;
+                /*
+                 * Uncompressed point: 0x04, then the coordinates x and y.
+                 */
+                let point: &[u8] = key_pair.public_key().as_ref();
+                (
+                    jsonwebtoken::EncodingKey::from_ec_der(pkcs8.as_ref()),
+                    serde_json::json!({
+                        "kty": "EC", "kid": kid, "alg": "ES256", "use": "sig", "crv": "P-256",
+                        "x": base64url(&point[1..33]), "y": base64url(&point[33..]),
+                    }),
+                )
+            }
+            jsonwebtoken::Algorithm::EdDSA => {
+                let pkcs8: ring::pkcs8::Document = ring::signature::Ed25519KeyPair::generate_pkcs8(&rng).unwrap();
+                let key_pair: ring::signature::Ed25519KeyPair =
+                    ring::signature::Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+                use ring::signature::KeyPair;
+                (
+                    jsonwebtoken::EncodingKey::from_ed_der(pkcs8.as_ref()),
+                    serde_json::json!({
+                        "kty": "OKP", "kid": kid, "alg": "EdDSA", "use": "sig", "crv": "Ed25519",
+                        "x": base64url(key_pair.public_key().as_ref()),
+                    }),
+                )
+            }
+            other => panic!("No test key for {other:?}"),
+        }
+    }
+
+    /// Validator trusting the keys. The returned directory holds the JWKS file.
+    fn validator(jwks: &[&serde_json::Value]) -> (tempfile::TempDir, super::Validator) {
+        let dir: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let path: std::path::PathBuf = dir.path().join("jwks.json");
+        std::fs::write(&path, serde_json::json!({ "keys": jwks }).to_string()).unwrap();
+
+        let validator: super::Validator = super::Validator {
+            jwks: crate::reload::Watched::load(path, super::Validator::parse_jwks).unwrap(),
+            issuer: ISSUER.to_owned(),
+            audience: AUDIENCE.to_owned(),
+            scopes_claim: "scope".to_owned(),
+        };
+        (dir, validator)
+    }
+
+    fn now() -> i64 {
+        chrono::Utc::now().timestamp()
+    }
+
+    /// Claims that are valid for an hour, with the given ones overridden.
+    fn claims(overrides: serde_json::Value) -> serde_json::Value {
+        let mut claims: serde_json::Value = serde_json::json!({
+            "iss": ISSUER,
+            "aud": AUDIENCE,
+            "sub": "alice",
+            "exp": now() + 3600,
+            "scope": "openid books:read",
+        });
+        for (claim, value) in overrides.as_object().unwrap() {
+            claims[claim] = value.clone();
+        }
+        claims
+    }
+
+    fn sign(
+        alg: jsonwebtoken::Algorithm,
+        kid: &str,
+        key: &jsonwebtoken::EncodingKey,
+        claims: &serde_json::Value,
+    ) -> String {
+        let mut header: jsonwebtoken::Header = jsonwebtoken::Header::new(alg);
+        header.kid = Some(kid.to_owned());
+        jsonwebtoken::encode(&header, claims, key).unwrap()
+    }
+
+    #[test]
+    fn accepts_each_algorithm() {
+        for alg in super::ALGORITHMS {
+            let (key, jwk) = key(alg, "k1");
+            let (_dir, validator) = validator(&[&jwk]);
+            let token: String = sign(alg, "k1", &key, &claims(serde_json::json!({})));
+
+            let principal: crate::web::auth::Principal = validator.validate(&token).unwrap();
+            assert_eq!(principal.name, "alice", "{alg:?}");
+            assert_eq!(principal.owner, format!("jwt:{ISSUER}|alice"), "{alg:?}");
+            assert_eq!(principal.scopes, vec![crate::web::auth::Scope::Read], "{alg:?}");
+        }
+    }
+
+    #[test]
+    fn chooses_key_by_id() {
+        let (rsa_key, rsa_jwk) = key(jsonwebtoken::Algorithm::RS256, "rsa");
+        let (ed_key, ed_jwk) = key(jsonwebtoken::Algorithm::EdDSA, "ed");
+        let (_dir, validator) = validator(&[&rsa_jwk, &ed_jwk]);
+        let claims: serde_json::Value = claims(serde_json::json!({}));
+
+        assert!(
+            validator
+                .validate(&sign(jsonwebtoken::Algorithm::RS256, "rsa", &rsa_key, &claims))
+                .is_ok()
+        );
+        assert!(
+            validator
+                .validate(&sign(jsonwebtoken::Algorithm::EdDSA, "ed", &ed_key, &claims))
+                .is_ok()
+        );
+        assert!(
+            validator
+                .validate(&sign(jsonwebtoken::Algorithm::EdDSA, "rsa", &ed_key, &claims))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_claims() {
+        let alg: jsonwebtoken::Algorithm = jsonwebtoken::Algorithm::ES256;
+        let (key, jwk) = key(alg, "k1");
+        let (_dir, validator) = validator(&[&jwk]);
+
+        for (overrides, expected) in [
+            (serde_json::json!({ "exp": now() - 3600 }), "ExpiredSignature"),
+            (serde_json::json!({ "nbf": now() + 3600 }), "ImmatureSignature"),
+            (
+                serde_json::json!({ "iss": "https://evil.example.com" }),
+                "InvalidIssuer",
+            ),
+            (serde_json::json!({ "aud": "other" }), "InvalidAudience"),
+            (serde_json::json!({ "sub": 42 }), "Missing required claim: sub"),
+        ] {
+            let token: String = sign(alg, "k1", &key, &claims(overrides));
+            assert_eq!(validator.validate(&token).err().as_deref(), Some(expected));
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_key_id() {
+        let (key, jwk) = key(jsonwebtoken::Algorithm::EdDSA, "k1");
+        let (_dir, validator) = validator(&[&jwk]);
+        let token: String = sign(
+            jsonwebtoken::Algorithm::EdDSA,
+            "k2",
+            &key,
+            &claims(serde_json::json!({})),
+        );
+
+        assert_eq!(
+            validator.validate(&token).err().as_deref(),
+            Some("No key \"k2\" in JWKS")
+        );
+    }
+
+    #[test]
+    fn rejects_unsigned_token() {
+        let (_key, jwk) = key(jsonwebtoken::Algorithm::EdDSA, "k1");
+        let (_dir, validator) = validator(&[&jwk]);
+        let header: String = base64url(br#"{"alg":"none","typ":"JWT","kid":"k1"}"#);
+        let payload: String = base64url(claims(serde_json::json!({})).to_string().as_bytes());
+
+        assert!(validator.validate(&format!("{header}.{payload}.")).is_err());
+    }
+
+    /// HS256 signed with the public key of the JWKS as the shared secret, as
+    /// would be accepted if the algorithm was taken from the token alone.
+    #[test]
+    fn rejects_symmetric_algorithm() {
+        let (_key, jwk) = key(jsonwebtoken::Algorithm::RS256, "k1");
+        let (_dir, validator) = validator(&[&jwk]);
+        let secret: jsonwebtoken::EncodingKey = jsonwebtoken::EncodingKey::from_secret(jwk.to_string().as_bytes());
+        let token: String = sign(
+            jsonwebtoken::Algorithm::HS256,
+            "k1",
+            &secret,
+            &claims(serde_json::json!({})),
+        );
+
+        assert_eq!(
+            validator.validate(&token).err().as_deref(),
+            Some("Algorithm HS256 is not accepted")
+        );
+    }
+}
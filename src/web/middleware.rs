@@ -0,0 +1,677 @@
+//! Middleware applied to all routes of the web server.
+
+pub static REQUEST_ID_HEADER: axum::http::HeaderName = axum::http::HeaderName::from_static("x-request-id");
+
+/// Longest request ID accepted from a client. Longer ones are replaced.
+const REQUEST_ID_MAX_LEN: usize = 128;
+
+/// Seconds that clients are told to wait before retrying a request that timed
+/// out.
+const TIMEOUT_RETRY_AFTER_SECS: u64 = 1;
+
+tokio::task_local! {
+    /// Deadline of the request being handled, set by [`timeout`].
+    pub static DEADLINE: std::time::Instant;
+}
+
+/// Correlates everything done on behalf of a single HTTP request, including the
+/// log records of the database actor. Available to handlers as an extension.
+#[derive(Clone)]
+pub struct RequestId(String);
+
+impl std::fmt::Display for RequestId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Accepts the request ID from header `X-Request-Id`, or generates one if the
+/// header is missing or unsuitable for logging, and echoes it back in the
+/// response. All log records written while handling the request carry the ID.
+pub async fn propagate_request_id(
+    mut request: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> axum::response::Response {
+    let request_id: String = match request
+        .headers()
+        .get(&REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| {
+            !value.is_empty() && value.len() <= REQUEST_ID_MAX_LEN && value.bytes().all(|byte| byte.is_ascii_graphic())
+        }) {
+        Some(n) => n.to_owned(),
+        None => uuid::Uuid::new_v4().to_string(),
+    };
+    request.extensions_mut().insert(RequestId(request_id.clone()));
+
+    let mut response: axum::response::Response =
+        crate::logg::WithMdc::new(next.run(request), crate::logg::MDC_KEY_REQUEST_ID, request_id.clone()).await;
+
+    match axum::http::HeaderValue::from_str(&request_id) {
+        Ok(value) => {
+            response.headers_mut().insert(&REQUEST_ID_HEADER, value);
+        }
+        Err(err) => {
+            log::error!("{err}");
+        }
+    }
+
+    response
+}
+
+/// Opens the span covering the handling of a request, continuing the trace of
+/// the client if the request has header `traceparent`.
+pub async fn trace_request(request: axum::extract::Request, next: axum::middleware::Next) -> axum::response::Response {
+    use tracing::Instrument;
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+    let request_id: String = match request.extensions().get::<RequestId>() {
+        Some(n) => n.to_string(),
+        None => String::new(),
+    };
+    let span: tracing::Span = tracing::info_span!(
+        "http.request",
+        http.request.method = %request.method(),
+        http.route = %matched_route(&request),
+        request_id = %request_id,
+        http.response.status_code = tracing::field::Empty,
+    );
+    let client_context: opentelemetry::Context = opentelemetry::global::get_text_map_propagator(|propagator| {
+        propagator.extract(&HeaderExtractor(request.headers()))
+    });
+    span.set_parent(client_context);
+
+    let response: axum::response::Response = next.run(request).instrument(span.clone()).await;
+
+    span.record("http.response.status_code", response.status().as_u16());
+
+    response
+}
+
+/// Answers with 503 Service Unavailable and header `Retry-After` once the
+/// request has taken longer than the timeout, dropping the handler along with
+/// whatever it was waiting for.
+/// The deadline is available to the handler via [`DEADLINE`], so that it can be
+/// passed on to the database actor.
+pub async fn timeout(
+    axum::extract::State(request_timeout): axum::extract::State<crate::web::RequestTimeout>,
+    request: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> axum::response::Response {
+    use axum::response::IntoResponse;
+
+    let crate::web::RequestTimeout(timeout) = request_timeout;
+    let deadline: std::time::Instant = std::time::Instant::now() + timeout;
+
+    match tokio::time::timeout(timeout, DEADLINE.scope(deadline, next.run(request))).await {
+        Ok(response) => response,
+        Err(_elapsed) => {
+            log::warn!("Service unavailable: Request not handled within {timeout:?}");
+            (
+                axum::http::StatusCode::SERVICE_UNAVAILABLE,
+                [(axum::http::header::RETRY_AFTER, TIMEOUT_RETRY_AFTER_SECS.to_string())],
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Records the count and latency of requests per route and response status.
+/// The liveness checks of [`crate::systemd`] are left out.
+pub async fn track_metrics(request: axum::extract::Request, next: axum::middleware::Next) -> axum::response::Response {
+    if crate::systemd::is_liveness_check(request.headers()) {
+        return next.run(request).await;
+    }
+    let started_at: std::time::Instant = std::time::Instant::now();
+    let method: axum::http::Method = request.method().clone();
+    let route: String = matched_route(&request);
+
+    let response: axum::response::Response = next.run(request).await;
+
+    let status: String = response.status().as_u16().to_string();
+    let labels: [&str; 3] = [method.as_str(), &route, &status];
+    crate::metrics::METRICS.http_requests.with_label_values(&labels).inc();
+    crate::metrics::METRICS
+        .http_request_duration
+        .with_label_values(&labels)
+        .observe(started_at.elapsed().as_secs_f64());
+
+    response
+}
+
+/// Writes one access log record per request. Registered inside
+/// [`propagate_request_id`] so that the request ID is available.
+///
+/// The response size is taken from header `Content-Length`, or from the body if
+/// its size is known up front, and is logged as `-` for streamed bodies.
+///
+/// The liveness checks of [`crate::systemd`] are not logged.
+pub async fn log_access(
+    axum::extract::State(format): axum::extract::State<crate::web::AccessLogFormat>,
+    request: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> axum::response::Response {
+    use axum::body::HttpBody;
+
+    if crate::systemd::is_liveness_check(request.headers()) {
+        return next.run(request).await;
+    }
+    let started_at: std::time::Instant = std::time::Instant::now();
+    let received_at: chrono::DateTime<chrono::Utc> = chrono::Utc::now();
+    let peer: String = match request
+        .extensions()
+        .get::<axum::extract::ConnectInfo<std::net::SocketAddr>>()
+    {
+        Some(axum::extract::ConnectInfo(address)) => address.ip().to_string(),
+        None => "-".to_owned(),
+    };
+    let method: axum::http::Method = request.method().clone();
+    let uri: axum::http::Uri = request.uri().clone();
+    let version: axum::http::Version = request.version();
+    let route: String = matched_route(&request);
+    let request_id: String = match request.extensions().get::<RequestId>() {
+        Some(n) => n.to_string(),
+        None => "-".to_owned(),
+    };
+    let referer: Option<String> = header_string(request.headers(), &axum::http::header::REFERER);
+    let user_agent: Option<String> = header_string(request.headers(), &axum::http::header::USER_AGENT);
+
+    let response: axum::response::Response = next.run(request).await;
+
+    let duration_ms: f64 = started_at.elapsed().as_secs_f64() * 1000.0;
+    let status: u16 = response.status().as_u16();
+    let bytes: Option<u64> = match response
+        .headers()
+        .get(axum::http::header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok())
+    {
+        Some(n) => Some(n),
+        None => response.body().size_hint().exact(),
+    };
+
+    let line: String = match format {
+        crate::web::AccessLogFormat::Combined => format!(
+            "{peer} - - [{}] \"{method} {uri} {version:?}\" {status} {} \"{}\" \"{}\" route=\"{route}\" duration_ms={duration_ms:.3} request_id={request_id}",
+            received_at.format("%d/%b/%Y:%H:%M:%S %z"),
+            match bytes {
+                Some(n) => n.to_string(),
+                None => "-".to_owned(),
+            },
+            escape_quoted(referer.as_deref().unwrap_or("-")),
+            escape_quoted(user_agent.as_deref().unwrap_or("-")),
+        ),
+        crate::web::AccessLogFormat::Json => serde_json::json!({
+            "time": received_at.to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            "peer": peer,
+            "method": method.as_str(),
+            "uri": uri.to_string(),
+            "protocol": format!("{version:?}"),
+            "route": route,
+            "status": status,
+            "bytes": bytes,
+            "duration_ms": duration_ms,
+            "request_id": request_id,
+            "referer": referer,
+            "user_agent": user_agent,
+        })
+        .to_string(),
+    };
+    log::info!(target: crate::logg::TARGET_ACCESS, "{line}");
+
+    response
+}
+
+fn header_string(headers: &axum::http::HeaderMap, name: &axum::http::HeaderName) -> Option<String> {
+    headers
+        .get(name)
+        .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned())
+}
+
+/// Escapes a value written inside double quotes in the Combined Log Format.
+fn escape_quoted(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Authenticates the client by header `Authorization: Bearer <token>`, where
+/// the token is either an API key, a JWT or the admin token configured at
+/// startup, and makes the [`crate::web::auth::Principal`] available to handlers.
+///
+/// Requests without the header are let through unauthenticated, leaving it to
+/// the handlers to require a principal. Requests with invalid credentials are
+/// rejected with 401 Unauthorized.
+pub async fn authenticate(
+    axum::extract::State(mut shared): axum::extract::State<crate::web::Shared>,
+    mut request: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> axum::response::Response {
+    use axum::response::IntoResponse;
+
+    let Some(authorization) = request.headers().get(axum::http::header::AUTHORIZATION) else {
+        return next.run(request).await;
+    };
+    /*
+     * Failed authentications are limited before authenticating, as checking
+     * credentials may involve the database.
+     */
+    let peer: String = peer_client(&request);
+    if let Some(rate_limiter) = &shared.rate_limiter {
+        let decision: crate::web::rate_limit::Decision =
+            rate_limiter.peek(&peer, crate::web::rate_limit::Class::FailedAuthentication);
+        if !decision.allowed {
+            log::warn!("Too many requests: Failed authentications of {peer} exceeded rate limit");
+            return too_many_requests(&decision);
+        }
+    }
+    let unauthorized = || {
+        if let Some(rate_limiter) = &shared.rate_limiter {
+            rate_limiter.check(&peer, crate::web::rate_limit::Class::FailedAuthentication);
+        }
+        crate::web::auth::unauthorized()
+    };
+    let Some(token) = authorization
+        .to_str()
+        .ok()
+        .and_then(|value| value.strip_prefix("Bearer "))
+    else {
+        log::warn!("Unauthorized: Header Authorization is not of scheme Bearer");
+        return unauthorized();
+    };
+
+    let principal: crate::web::auth::Principal = if let Some(admin_token) = &shared.admin_token
+        && crate::web::auth::constant_time_eq(token.as_bytes(), admin_token.as_bytes())
+    {
+        crate::web::auth::Principal::admin_token()
+    } else if crate::web::auth::is_api_key(token) {
+        let key_hash: Vec<u8> = crate::web::auth::hash_api_key(token);
+        let request_id: RequestId = match request.extensions().get::<RequestId>() {
+            Some(n) => n.clone(),
+            None => RequestId(String::new()),
+        };
+        match shared
+            .db_client
+            .select_api_key_not_revoked_by_hash(&request_id, key_hash)
+            .await
+        {
+            Ok(Some(api_key)) => crate::web::auth::Principal::from_api_key(&api_key),
+            Ok(None) => {
+                log::warn!("Unauthorized: Unknown or revoked API key");
+                return unauthorized();
+            }
+            Err(err) => {
+                return err.into_response();
+            }
+        }
+    } else if let Some(jwt_validator) = &shared.jwt_validator {
+        match jwt_validator.validate(token) {
+            Ok(n) => n,
+            Err(err) => {
+                log::warn!("Unauthorized: Invalid JWT: {err}");
+                return unauthorized();
+            }
+        }
+    } else {
+        log::warn!("Unauthorized: Unrecognized bearer token");
+        return unauthorized();
+    };
+    /*
+     * E.g. the subject of a JWT is chosen by the identity provider, and could
+     * be too long to be stored as the owner of books.
+     */
+    if principal.owner.chars().count() > crate::web::auth::OWNER_MAX_CHARS {
+        log::warn!(
+            "Unauthorized: Identity of {} is longer than {} characters",
+            principal.id,
+            crate::web::auth::OWNER_MAX_CHARS
+        );
+        return unauthorized();
+    }
+
+    request.extensions_mut().insert(principal);
+
+    next.run(request).await
+}
+
+/// Rejects requests with 429 Too Many Requests once the client has used up its
+/// budget, see [`crate::web::rate_limit`]. Authenticated clients are limited
+/// per principal, others per IP address. Headers `RateLimit-Limit`,
+/// `RateLimit-Remaining` and `RateLimit-Reset` tell clients where they stand.
+///
+/// Failed authentications are limited separately by [`authenticate`].
+pub async fn rate_limit(
+    axum::extract::State(shared): axum::extract::State<crate::web::Shared>,
+    request: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> axum::response::Response {
+    let Some(rate_limiter) = &shared.rate_limiter else {
+        return next.run(request).await;
+    };
+
+    let client: String = match request.extensions().get::<crate::web::auth::Principal>() {
+        Some(principal) => format!("principal:{}", principal.owner),
+        None => peer_client(&request),
+    };
+    let class: crate::web::rate_limit::Class = crate::web::rate_limit::Class::of(request.method());
+    let decision: crate::web::rate_limit::Decision = rate_limiter.check(&client, class);
+
+    if !decision.allowed {
+        log::warn!("Too many requests: Rate limit of {client} exceeded");
+        return too_many_requests(&decision);
+    }
+    let mut response: axum::response::Response = next.run(request).await;
+    insert_rate_limit_headers(response.headers_mut(), &decision);
+
+    response
+}
+
+/// Client for rate limiting by IP address.
+fn peer_client(request: &axum::extract::Request) -> String {
+    match request
+        .extensions()
+        .get::<axum::extract::ConnectInfo<std::net::SocketAddr>>()
+    {
+        Some(axum::extract::ConnectInfo(address)) => format!("ip:{}", address.ip()),
+        None => "unknown".to_owned(),
+    }
+}
+
+fn too_many_requests(decision: &crate::web::rate_limit::Decision) -> axum::response::Response {
+    use axum::response::IntoResponse;
+
+    let mut response: axum::response::Response = (
+        axum::http::StatusCode::TOO_MANY_REQUESTS,
+        [(axum::http::header::RETRY_AFTER, decision.retry_after_secs.to_string())],
+    )
+        .into_response();
+    insert_rate_limit_headers(response.headers_mut(), decision);
+
+    response
+}
+
+fn insert_rate_limit_headers(headers: &mut axum::http::HeaderMap, decision: &crate::web::rate_limit::Decision) {
+    headers.insert("ratelimit-limit", decision.limit.into());
+    headers.insert("ratelimit-remaining", decision.remaining.into());
+    headers.insert("ratelimit-reset", decision.reset_secs.into());
+}
+
+/// Response headers that browser apps may read, in addition to the ones that
+/// are always readable, e.g. `Content-Type`.
+const CORS_EXPOSED_HEADERS: &str = "x-request-id, retry-after, ratelimit-limit, ratelimit-remaining, ratelimit-reset";
+
+/// Answers CORS preflight requests, and adds the CORS headers to responses to
+/// allowed origins, see [`crate::web::cors`]. Requests from other origins, and
+/// preflights asking for a method or headers not on the list, are rejected with
+/// 403 Forbidden and a message naming what's not allowed. Requests without
+/// header `Origin` are let through as is, apart from header `Vary`.
+pub async fn cors(
+    axum::extract::State(shared): axum::extract::State<crate::web::Shared>,
+    request: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> axum::response::Response {
+    match &shared.cors {
+        Some(cors) => apply_cors(&cors.policy(), request, next).await,
+        None => next.run(request).await,
+    }
+}
+
+async fn apply_cors(
+    policy: &crate::web::cors::Policy,
+    request: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> axum::response::Response {
+    use axum::response::IntoResponse;
+
+    /*
+     * Even responses to requests without an origin vary by it, as a cache
+     * could otherwise answer a later cross-origin request with them.
+     */
+    let Some(origin) = request.headers().get(axum::http::header::ORIGIN).cloned() else {
+        return vary_by_origin(next.run(request).await);
+    };
+    let origin_str: String = String::from_utf8_lossy(origin.as_bytes()).into_owned();
+
+    if !policy.allows_origin(&origin_str) {
+        return cors_forbidden(format!("Origin {origin_str:?} is not allowed by the CORS policy"));
+    }
+
+    let preflight_method: Option<String> = match *request.method() {
+        axum::http::Method::OPTIONS => {
+            header_string(request.headers(), &axum::http::header::ACCESS_CONTROL_REQUEST_METHOD)
+        }
+        _ => None,
+    };
+
+    let mut response: axum::response::Response = match preflight_method {
+        Some(method) => {
+            if !policy.allows_method(&method) {
+                return cors_forbidden(format!(
+                    "Method {method:?} is not allowed by the CORS policy for origin {origin_str:?}"
+                ));
+            }
+            let requested_headers: Option<axum::http::HeaderValue> = request
+                .headers()
+                .get(axum::http::header::ACCESS_CONTROL_REQUEST_HEADERS)
+                .cloned();
+            if let Some(requested_headers) = &requested_headers
+                && let Err(header) = policy.allows_headers(&String::from_utf8_lossy(requested_headers.as_bytes()))
+            {
+                return cors_forbidden(format!(
+                    "Header {header:?} is not allowed by the CORS policy for origin {origin_str:?}"
+                ));
+            }
+
+            let mut response: axum::response::Response = axum::http::StatusCode::NO_CONTENT.into_response();
+            let headers: &mut axum::http::HeaderMap = response.headers_mut();
+            match axum::http::HeaderValue::from_str(&policy.methods.join(", ")) {
+                Ok(value) => {
+                    headers.insert(axum::http::header::ACCESS_CONTROL_ALLOW_METHODS, value);
+                }
+                Err(err) => {
+                    log::error!("{err}");
+                }
+            }
+            if let Some(requested_headers) = requested_headers {
+                headers.insert(axum::http::header::ACCESS_CONTROL_ALLOW_HEADERS, requested_headers);
+            }
+            headers.insert(axum::http::header::ACCESS_CONTROL_MAX_AGE, policy.max_age_secs.into());
+            response
+        }
+        None => {
+            let mut response: axum::response::Response = next.run(request).await;
+            response.headers_mut().insert(
+                axum::http::header::ACCESS_CONTROL_EXPOSE_HEADERS,
+                axum::http::HeaderValue::from_static(CORS_EXPOSED_HEADERS),
+            );
+            response
+        }
+    };
+
+    /*
+     * The origin is echoed rather than answered with `*`, as the latter doesn't
+     * work with credentials.
+     */
+    let headers: &mut axum::http::HeaderMap = response.headers_mut();
+    headers.insert(axum::http::header::ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+    if policy.credentials {
+        headers.insert(
+            axum::http::header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
+            axum::http::HeaderValue::from_static("true"),
+        );
+    }
+
+    vary_by_origin(response)
+}
+
+fn cors_forbidden(message: String) -> axum::response::Response {
+    use axum::response::IntoResponse;
+
+    log::warn!("Forbidden: {message}");
+    vary_by_origin((axum::http::StatusCode::FORBIDDEN, message).into_response())
+}
+
+/// Responses depend on the origin, so caches must not share them across
+/// origins.
+fn vary_by_origin(mut response: axum::response::Response) -> axum::response::Response {
+    response
+        .headers_mut()
+        .append(axum::http::header::VARY, axum::http::HeaderValue::from_static("origin"));
+    response
+}
+
+/// Route template, e.g. `/api/books/v1/{id}`. Requests that match no route
+/// share a single placeholder, so that arbitrary paths don't end up in metrics
+/// or span names.
+fn matched_route(request: &axum::extract::Request) -> String {
+    match request.extensions().get::<axum::extract::MatchedPath>() {
+        Some(matched_path) => matched_path.as_str().to_owned(),
+        None => "unmatched".to_owned(),
+    }
+}
+
+struct HeaderExtractor<'a>(&'a axum::http::HeaderMap);
+
+impl opentelemetry::propagation::Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    /// Serves `GET /` behind the CORS middleware with a policy allowing origin
+    /// `https://app.example`, method `PUT` and header `content-type`.
+    async fn serve() -> std::net::SocketAddr {
+        let policy: std::sync::Arc<crate::web::cors::Policy> = std::sync::Arc::new(crate::web::cors::Policy {
+            origins: vec!["https://app.example".to_owned()],
+            methods: vec!["PUT".to_owned()],
+            headers: vec!["content-type".to_owned()],
+            credentials: false,
+            max_age_secs: 600,
+        });
+        let router: axum::Router = axum::Router::new()
+            .route("/", axum::routing::get(|| async { "ok" }))
+            .layer(axum::middleware::from_fn(
+                move |request: axum::extract::Request, next: axum::middleware::Next| {
+                    let policy: std::sync::Arc<crate::web::cors::Policy> = policy.clone();
+                    async move { super::apply_cors(&policy, request, next).await }
+                },
+            ));
+        let listener: tokio::net::TcpListener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address: std::net::SocketAddr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        address
+    }
+
+    #[tokio::test]
+    async fn answers_timed_out_request_with_retry_after() {
+        let router: axum::Router = axum::Router::new()
+            .route(
+                "/",
+                axum::routing::get(|| async {
+                    tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+                    "late"
+                }),
+            )
+            .layer(axum::middleware::from_fn_with_state(
+                crate::web::RequestTimeout(std::time::Duration::from_millis(50)),
+                super::timeout,
+            ));
+        let listener: tokio::net::TcpListener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address: std::net::SocketAddr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await });
+
+        let response: crate::web::testing::Response = crate::web::testing::send(address, "GET", "/", &[], "").await;
+
+        assert_eq!(response.status, 503);
+        assert_eq!(response.header("retry-after"), Some("1"));
+    }
+
+    #[tokio::test]
+    async fn names_what_cors_policy_rejects() {
+        let address: std::net::SocketAddr = serve().await;
+
+        let response: crate::web::testing::Response =
+            crate::web::testing::send(address, "GET", "/", &["origin: https://evil.example"], "").await;
+        assert_eq!(response.status, 403);
+        assert_eq!(response.header("vary"), Some("origin"));
+        assert_eq!(
+            response.body,
+            r#"Origin "https://evil.example" is not allowed by the CORS policy"#
+        );
+
+        let response: crate::web::testing::Response = crate::web::testing::send(
+            address,
+            "OPTIONS",
+            "/",
+            &["origin: https://app.example", "access-control-request-method: DELETE"],
+            "",
+        )
+        .await;
+        assert_eq!(response.status, 403);
+        assert_eq!(
+            response.body,
+            r#"Method "DELETE" is not allowed by the CORS policy for origin "https://app.example""#
+        );
+
+        let response: crate::web::testing::Response = crate::web::testing::send(
+            address,
+            "OPTIONS",
+            "/",
+            &[
+                "origin: https://app.example",
+                "access-control-request-method: PUT",
+                "access-control-request-headers: Content-Type, X-Custom",
+            ],
+            "",
+        )
+        .await;
+        assert_eq!(response.status, 403);
+        assert_eq!(
+            response.body,
+            r#"Header "x-custom" is not allowed by the CORS policy for origin "https://app.example""#
+        );
+    }
+
+    #[tokio::test]
+    async fn varies_by_origin_with_and_without_origin() {
+        let address: std::net::SocketAddr = serve().await;
+
+        let response: crate::web::testing::Response = crate::web::testing::send(address, "GET", "/", &[], "").await;
+        assert_eq!(response.status, 200);
+        assert_eq!(response.header("vary"), Some("origin"));
+        assert!(
+            !response
+                .headers
+                .iter()
+                .any(|(name, _value)| name.starts_with("access-control-")),
+            "{:?}",
+            response.headers
+        );
+        assert_eq!(response.body, "ok");
+
+        let response: crate::web::testing::Response =
+            crate::web::testing::send(address, "GET", "/", &["origin: https://app.example"], "").await;
+        assert_eq!(response.status, 200);
+        assert_eq!(response.header("vary"), Some("origin"));
+        assert_eq!(
+            response.header("access-control-allow-origin"),
+            Some("https://app.example")
+        );
+
+        let response: crate::web::testing::Response = crate::web::testing::send(
+            address,
+            "OPTIONS",
+            "/",
+            &["origin: https://app.example", "access-control-request-method: PUT"],
+            "",
+        )
+        .await;
+        assert_eq!(response.status, 204);
+        assert_eq!(response.header("vary"), Some("origin"));
+        assert_eq!(response.header("access-control-allow-methods"), Some("PUT"));
+    }
+}
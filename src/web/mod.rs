@@ -1,54 +1,377 @@
+use crate::web::handlers::admin;
 use crate::web::handlers::books_v1;
+use crate::web::handlers::health;
+use crate::web::handlers::metrics;
 
+mod auth;
+mod bulk_body;
+pub mod cors;
 mod db_client;
 mod handlers;
+pub mod idempotency;
+pub mod jwt;
+mod middleware;
+pub mod rate_limit;
+#[cfg(test)]
+pub mod testing;
+pub mod tls;
+mod validation;
+
+/// How long the web server keeps serving after the global shutdown signal has
+/// been activated, while reporting itself as not ready.
+const SHUTDOWN_DRAIN_PERIOD: std::time::Duration = std::time::Duration::from_secs(5);
+
+#[derive(Clone)]
+pub struct Config {
+    pub listen_address: String,
+    pub access_log_format: AccessLogFormat,
+    /// Bearer token granting scope `books:admin`, e.g. for issuing the first
+    /// API keys. `None` disables it.
+    pub admin_token: Option<std::sync::Arc<str>>,
+    /// `None` if JWTs are not accepted.
+    pub jwt_validator: Option<std::sync::Arc<jwt::Validator>>,
+    /// `None` if requests are not rate limited.
+    pub rate_limiter: Option<std::sync::Arc<rate_limit::Limiter>>,
+    /// How long a request waits for room in the database actor's queue, see
+    /// [`crate::db::Queue`].
+    pub db_admission_timeout: std::time::Duration,
+    pub request_timeout: RequestTimeout,
+    pub request_body_limit: RequestBodyLimit,
+    pub bulk_limits: BulkLimits,
+    pub idempotency_key_ttl: idempotency::Ttl,
+    pub content_encoding: ContentEncoding,
+    /// `None` if the web server speaks plain HTTP.
+    pub tls: Option<std::sync::Arc<tls::Tls>>,
+    /// `None` if requests from other origins are not allowed.
+    pub cors: Option<std::sync::Arc<cors::Cors>>,
+}
+
+impl Config {
+    /// Reads the environment variables of each part, see e.g.
+    /// [`RequestTimeout::from_env`], and `ADMIN_TOKEN`: Unset or empty by
+    /// default, i.e. disabled. The program exits with code 48 if any of them is
+    /// invalid.
+    pub fn from_env(db_admission_timeout: std::time::Duration) -> Result<Self, String> {
+        Ok(Self {
+            listen_address: "127.0.0.1:8080".to_owned(),
+            access_log_format: AccessLogFormat::from_env()?,
+            admin_token: std::env::var("ADMIN_TOKEN")
+                .ok()
+                .filter(|token| !token.is_empty())
+                .map(std::sync::Arc::from),
+            jwt_validator: jwt::Validator::from_env()?.map(std::sync::Arc::new),
+            rate_limiter: rate_limit::Limiter::from_env()?.map(std::sync::Arc::new),
+            db_admission_timeout,
+            request_timeout: RequestTimeout::from_env()?,
+            request_body_limit: RequestBodyLimit::from_env()?,
+            bulk_limits: BulkLimits::from_env()?,
+            idempotency_key_ttl: idempotency::Ttl::from_env()?,
+            content_encoding: ContentEncoding::from_env()?,
+            tls: tls::Tls::from_env()?.map(std::sync::Arc::new),
+            cors: cors::Cors::from_env()?.map(std::sync::Arc::new),
+        })
+    }
+}
+
+/// Format of the access log records, one per HTTP request, written with log
+/// target [`crate::logg::TARGET_ACCESS`].
+#[derive(Clone, Copy)]
+pub enum AccessLogFormat {
+    /// Combined Log Format as known from Apache and nginx, followed by the
+    /// matched route, the duration in milliseconds and the request ID.
+    Combined,
+    /// One JSON object per line.
+    Json,
+}
+
+impl AccessLogFormat {
+    /// Reads environment variable `ACCESS_LOG_FORMAT`: `combined` (default) or
+    /// `json`.
+    pub fn from_env() -> Result<Self, String> {
+        match std::env::var("ACCESS_LOG_FORMAT").as_deref() {
+            Err(std::env::VarError::NotPresent) | Ok("combined") => Ok(Self::Combined),
+            Ok("json") => Ok(Self::Json),
+            Ok(other) => Err(format!(
+                "Invalid ACCESS_LOG_FORMAT {other:?}, expected \"combined\" or \"json\""
+            )),
+            Err(err) => Err(format!("Invalid ACCESS_LOG_FORMAT: {err}")),
+        }
+    }
+}
+
+/// How long a request may take to handle before it's answered with 503
+/// Service Unavailable. Queries sent on behalf of the request share the
+/// deadline, see [`crate::db::Envelope::deadline`].
+#[derive(Clone, Copy)]
+pub struct RequestTimeout(pub std::time::Duration);
+
+impl RequestTimeout {
+    /// Reads environment variable `REQUEST_TIMEOUT_MS`. Defaults to 5000.
+    pub fn from_env() -> Result<Self, String> {
+        let millis: u64 = crate::env::positive_or("REQUEST_TIMEOUT_MS", 5000)?;
+        Ok(Self(std::time::Duration::from_millis(millis)))
+    }
+}
+
+/// Limit of the size of request bodies as received, i.e. before any
+/// decompression, see [`ContentEncoding`]. Bodies exceeding it are answered
+/// with 413 Payload Too Large.
+#[derive(Clone, Copy)]
+pub struct RequestBodyLimit(pub usize);
+
+impl RequestBodyLimit {
+    /// Reads environment variable `REQUEST_BODY_MAX_BYTES`. Defaults to 1048576
+    /// (1 MiB).
+    pub fn from_env() -> Result<Self, String> {
+        Ok(Self(crate::env::positive_or("REQUEST_BODY_MAX_BYTES", 1024 * 1024)?))
+    }
+}
+
+/// Compression of response bodies, negotiated with `Accept-Encoding`, and
+/// decompression of request bodies with `Content-Encoding`. Supported
+/// encodings are `gzip`, `br` and `zstd`.
+#[derive(Clone, Copy)]
+pub struct ContentEncoding {
+    /// Response bodies smaller than this are not compressed, as the overhead
+    /// would outweigh the savings.
+    pub compression_min_bytes: u16,
+    /// Limit of the size of request bodies after decompression, so that a
+    /// small compressed body can't expand to exhaust memory ("zip bomb").
+    /// Bodies exceeding it are answered with 413 Payload Too Large.
+    pub max_decompressed_bytes: usize,
+}
+
+impl ContentEncoding {
+    /// Reads the following environment variables:
+    ///
+    /// - `COMPRESSION_MIN_BYTES`: Defaults to 1024.
+    /// - `REQUEST_BODY_MAX_DECOMPRESSED_BYTES`: Defaults to 2097152 (2 MiB).
+    pub fn from_env() -> Result<Self, String> {
+        let compression_min_bytes: u16 = crate::env::parse_or("COMPRESSION_MIN_BYTES", 1024)?;
+        let max_decompressed_bytes: usize =
+            crate::env::positive_or("REQUEST_BODY_MAX_DECOMPRESSED_BYTES", 2 * 1024 * 1024)?;
+
+        Ok(Self {
+            compression_min_bytes,
+            max_decompressed_bytes,
+        })
+    }
+}
+
+/// Limits of bulk requests, see [`books_v1::post_bulk`], in place of
+/// [`RequestTimeout`], [`RequestBodyLimit`] and
+/// [`ContentEncoding::max_decompressed_bytes`]. Their bodies are applied in
+/// batches as they arrive rather than buffered, so they can be much larger.
+#[derive(Clone, Copy)]
+pub struct BulkLimits {
+    pub request_timeout: RequestTimeout,
+    pub request_body_limit: RequestBodyLimit,
+    pub max_decompressed_bytes: usize,
+}
+
+impl BulkLimits {
+    /// Reads the following environment variables:
+    ///
+    /// - `BULK_REQUEST_TIMEOUT_MS`: Defaults to 60000.
+    /// - `BULK_REQUEST_BODY_MAX_BYTES`: Defaults to 67108864 (64 MiB).
+    /// - `BULK_REQUEST_BODY_MAX_DECOMPRESSED_BYTES`: Defaults to 134217728
+    ///   (128 MiB).
+    pub fn from_env() -> Result<Self, String> {
+        let timeout_millis: u64 = crate::env::positive_or("BULK_REQUEST_TIMEOUT_MS", 60_000)?;
+        let max_bytes: usize = crate::env::positive_or("BULK_REQUEST_BODY_MAX_BYTES", 64 * 1024 * 1024)?;
+        let max_decompressed_bytes: usize =
+            crate::env::positive_or("BULK_REQUEST_BODY_MAX_DECOMPRESSED_BYTES", 128 * 1024 * 1024)?;
+
+        Ok(Self {
+            request_timeout: RequestTimeout(std::time::Duration::from_millis(timeout_millis)),
+            request_body_limit: RequestBodyLimit(max_bytes),
+            max_decompressed_bytes,
+        })
+    }
+}
 
 #[derive(Clone)]
 struct Shared {
     db_client: db_client::DatabaseClient,
+    /// Global shutdown signal, observed to stop accepting traffic as soon as
+    /// shutdown has been initiated.
+    term_token: tokio_util::sync::CancellationToken,
+    log_levels: std::sync::Arc<crate::logg::LevelControl>,
+    admin_token: Option<std::sync::Arc<str>>,
+    jwt_validator: Option<std::sync::Arc<jwt::Validator>>,
+    rate_limiter: Option<std::sync::Arc<rate_limit::Limiter>>,
+    cors: Option<std::sync::Arc<cors::Cors>>,
+    idempotency_key_ttl: idempotency::Ttl,
 }
 
 impl Shared {
-    pub fn init(tx_query: tokio::sync::mpsc::Sender<crate::db::Query>) -> Self {
+    pub fn init(
+        tx_query: tokio::sync::mpsc::Sender<crate::db::Envelope>,
+        term_token: tokio_util::sync::CancellationToken,
+        log_levels: std::sync::Arc<crate::logg::LevelControl>,
+        config: &Config,
+    ) -> Self {
         Self {
-            db_client: db_client::DatabaseClient::new(tx_query),
+            db_client: db_client::DatabaseClient::new(tx_query, config.db_admission_timeout),
+            term_token,
+            log_levels,
+            admin_token: config.admin_token.clone(),
+            jwt_validator: config.jwt_validator.clone(),
+            rate_limiter: config.rate_limiter.clone(),
+            cors: config.cors.clone(),
+            idempotency_key_ttl: config.idempotency_key_ttl,
         }
     }
 }
 
 pub struct Actor {
     term: crate::term::Handle,
+    systemd: crate::systemd::Handle,
 
     listen_address: String,
+    tls: Option<std::sync::Arc<tls::Tls>>,
+    rate_limiter: Option<std::sync::Arc<rate_limit::Limiter>>,
     router: axum::Router,
 }
 
 impl Actor {
     pub fn init(
         term: crate::term::Handle,
-        listen_address: &str,
-        tx_query: tokio::sync::mpsc::Sender<crate::db::Query>,
+        systemd: crate::systemd::Handle,
+        config: &Config,
+        tx_query: tokio::sync::mpsc::Sender<crate::db::Envelope>,
+        log_levels: std::sync::Arc<crate::logg::LevelControl>,
     ) -> Self {
-        let state: Shared = Shared::init(tx_query);
+        let state: Shared = Shared::init(tx_query, term.clone().token(), log_levels, config);
 
-        let router: axum::Router = axum::Router::new()
+        let router: axum::Router<Shared> = axum::Router::new()
             /*
              * Create-read-update-delete (CRUD) API for books, v1.
              */
             .route("/api/books/v1/genre/{genre}", axum::routing::post(books_v1::post_one))
             .route("/api/books/v1", axum::routing::get(books_v1::get_all))
             .route("/api/books/v1/{id}", axum::routing::get(books_v1::get_one_by_id))
+            .route("/api/books/v1/{id}", axum::routing::put(books_v1::put_one_by_id))
             .route("/api/books/v1/{id}", axum::routing::delete(books_v1::delete_one_by_id))
+            /*
+             * Administration, requiring scope `books:admin`.
+             */
+            .route("/admin/log-level", axum::routing::put(admin::put_log_level))
+            .route("/admin/api-keys", axum::routing::post(admin::post_one_api_key))
+            .route("/admin/api-keys", axum::routing::get(admin::get_all_api_keys))
+            .route(
+                "/admin/api-keys/{id}",
+                axum::routing::delete(admin::delete_one_api_key_by_id),
+            )
+            /*
+             * Only the routes above are rate limited, so that probes keep
+             * working for throttled clients, e.g. on the same host.
+             */
+            .route_layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                middleware::rate_limit,
+            ))
+            /*
+             * Health probes, e.g. for a load balancer.
+             */
+            .route("/healthz", axum::routing::get(health::get_liveness))
+            .route("/readyz", axum::routing::get(health::get_readiness))
+            .route("/metrics", axum::routing::get(metrics::get_all));
+        let router: axum::Router<Shared> = Self::limit(
+            router,
+            &state,
+            config,
+            config.request_timeout,
+            config.request_body_limit,
+            config.content_encoding.max_decompressed_bytes,
+        );
+
+        /*
+         * Bulk requests are exempt from the limits above, having their own.
+         */
+        let bulk_router: axum::Router<Shared> = axum::Router::new()
+            .route("/api/books/v1/bulk", axum::routing::post(books_v1::post_bulk))
+            .route_layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                middleware::rate_limit,
+            ));
+        let bulk_router: axum::Router<Shared> = Self::limit(
+            bulk_router,
+            &state,
+            config,
+            config.bulk_limits.request_timeout,
+            config.bulk_limits.request_body_limit,
+            config.bulk_limits.max_decompressed_bytes,
+        );
+
+        let router: axum::Router = router
+            .merge(bulk_router)
+            /*
+             * Outside of authentication, as preflight requests don't carry
+             * credentials.
+             */
+            .layer(axum::middleware::from_fn_with_state(state.clone(), middleware::cors))
+            .layer(axum::middleware::from_fn(middleware::track_metrics))
+            .layer(axum::middleware::from_fn(middleware::trace_request))
+            .layer(axum::middleware::from_fn_with_state(
+                config.access_log_format,
+                middleware::log_access,
+            ))
+            .layer(axum::middleware::from_fn(middleware::propagate_request_id))
             .with_state(state);
 
         Self {
             term,
+            systemd,
 
             router,
-            listen_address: listen_address.to_owned(),
+            listen_address: config.listen_address.clone(),
+            tls: config.tls.clone(),
+            rate_limiter: config.rate_limiter.clone(),
         }
     }
 
+    /// Applies the limits of the size of request bodies and of the time taken
+    /// to handle requests, along with the layers inside of them, i.e.
+    /// authentication and compression.
+    fn limit(
+        router: axum::Router<Shared>,
+        state: &Shared,
+        config: &Config,
+        request_timeout: RequestTimeout,
+        request_body_limit: RequestBodyLimit,
+        max_decompressed_bytes: usize,
+    ) -> axum::Router<Shared> {
+        use tower_http::compression::Predicate;
+
+        router
+            /*
+             * The body limit is inside of the decompression, i.e. it applies
+             * to the decompressed size. It replaces axum's default limit, which
+             * would otherwise cap it at 2 MiB regardless of configuration.
+             */
+            .layer(tower_http::limit::RequestBodyLimitLayer::new(max_decompressed_bytes))
+            .layer(axum::extract::DefaultBodyLimit::disable())
+            .layer(tower_http::decompression::RequestDecompressionLayer::new())
+            /*
+             * Applies to all requests, compressed or not.
+             */
+            .layer(tower_http::limit::RequestBodyLimitLayer::new(request_body_limit.0))
+            .layer(tower_http::compression::CompressionLayer::new().compress_when(
+                tower_http::compression::predicate::DefaultPredicate::new().and(
+                    tower_http::compression::predicate::SizeAbove::new(config.content_encoding.compression_min_bytes),
+                ),
+            ))
+            .layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                middleware::authenticate,
+            ))
+            .layer(axum::middleware::from_fn_with_state(
+                request_timeout,
+                middleware::timeout,
+            ))
+    }
+
     pub async fn work(self) -> Summary {
         let listener: tokio::net::TcpListener = match tokio::net::TcpListener::bind(self.listen_address).await {
             Ok(n) => n,
@@ -60,21 +383,73 @@ impl Actor {
                 return Summary;
             }
         };
+        match listener.local_addr() {
+            Ok(local_address) => {
+                log::info!(
+                    "Listening on {local_address} ({})",
+                    if self.tls.is_some() { "HTTPS" } else { "HTTP" }
+                );
+                self.systemd.notify_listening(local_address, self.tls.is_some()).await;
+            }
+            Err(err) => {
+                log::error!("{err}");
+            }
+        }
+
+        /*
+         * Once the global shutdown signal is activated, keep serving for a
+         * while, so that load balancers observe the failing readiness probe
+         * and drain traffic before the listener is closed.
+         */
+        let term_token: tokio_util::sync::CancellationToken = self.term.token();
+        let drained = async move {
+            term_token.cancelled().await;
+            log::info!("Draining traffic for {SHUTDOWN_DRAIN_PERIOD:?} before shutting down");
+            tokio::time::sleep(SHUTDOWN_DRAIN_PERIOD).await;
+        };
+
+        /*
+         * Peer addresses of the connections are made available to handlers and
+         * middleware, e.g. for the access log.
+         */
+        let service = self
+            .router
+            .into_make_service_with_connect_info::<std::net::SocketAddr>();
+        let serving = async {
+            match self.tls {
+                Some(tls) => {
+                    use axum::serve::ListenerExt;
 
-        self.term
-            .token()
-            .run_until_cancelled(async {
-                if let Err(err) = axum::serve(listener, self.router).await {
                     /*
-                     * From axum's docs (v0.8.4):
-                     *
-                     * > Although this future resolves to `io::Result<()>`, it
-                     * > will never actually complete or return an error.
+                     * axum provides the peer address as connect info only for
+                     * its own listeners, which a no-op tap turns this one into.
                      */
-                    unreachable!("axum::serve never completes or returns an error: {err}");
-                };
-            })
-            .await;
+                    let listener = tls::Listener::new(listener, tls).tap_io(|_| ());
+                    axum::serve(listener, service).with_graceful_shutdown(drained).await
+                }
+                None => axum::serve(listener, service).with_graceful_shutdown(drained).await,
+            }
+        };
+        let evicting = async {
+            match &self.rate_limiter {
+                Some(n) => n.evict_stale_periodically().await,
+                None => std::future::pending().await,
+            }
+        };
+        let served: std::io::Result<()> = tokio::select! {
+            served = serving => served,
+            _ = evicting => unreachable!("Eviction of rate limit buckets never returns"),
+        };
+        if let Err(err) = served {
+            /*
+             * From axum's docs (v0.8.4):
+             *
+             * > Similarly to [`serve`], although this future resolves to
+             * > `io::Result<()>`, it will never error. It returns `Ok(())` only
+             * > after the `signal` future completes.
+             */
+            unreachable!("axum::serve with graceful shutdown never returns an error: {err}");
+        };
 
         Summary
     }
@@ -0,0 +1,409 @@
+//! Token bucket rate limiting of clients, so that no single client can keep the
+//! database actor busy with a flood of queries. Each client has a bucket per
+//! [`Class`] of requests, holding up to `capacity` tokens and refilled at a
+//! constant rate. Each request takes one token, or is rejected if there's none.
+
+/// Most buckets kept. Once reached, the oldest bucket is evicted for each new
+/// one, which resets the budget of its client. Buckets of idle clients are
+/// evicted every [`EVICTION_INTERVAL`] well before that.
+const MAX_BUCKETS: usize = 10_000;
+
+/// How often buckets that have refilled completely are evicted.
+const EVICTION_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Class {
+    /// Safe methods, e.g. `GET`.
+    Read,
+    Write,
+    /// Failed authentications, limited per IP address before authenticating,
+    /// so that guessing credentials can't keep the database actor busy.
+    FailedAuthentication,
+}
+
+impl Class {
+    pub fn of(method: &axum::http::Method) -> Self {
+        if method.is_safe() { Class::Read } else { Class::Write }
+    }
+}
+
+#[derive(serde::Deserialize, Clone, Copy)]
+#[serde(deny_unknown_fields)]
+pub struct Budget {
+    /// Largest burst of requests allowed.
+    pub capacity: u32,
+    /// Tokens added per second, i.e. the sustained rate of requests allowed.
+    pub refill_per_sec: f64,
+}
+
+/// Contents of the limits file, e.g.:
+///
+/// ```json
+/// { "read": { "capacity": 100, "refill_per_sec": 10 }, "write": { "capacity": 20, "refill_per_sec": 1 } }
+/// ```
+///
+/// `failed_authentication` defaults to a capacity of 10 and a refill rate of 1.
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Limits {
+    pub read: Budget,
+    pub write: Budget,
+    #[serde(default = "Limits::default_failed_authentication")]
+    pub failed_authentication: Budget,
+}
+
+impl Limits {
+    fn parse(contents: &[u8]) -> Result<Self, String> {
+        let limits: Limits = serde_json::from_slice(contents).map_err(|err| err.to_string())?;
+        for budget in [limits.read, limits.write, limits.failed_authentication] {
+            if budget.capacity == 0 || budget.refill_per_sec <= 0.0 {
+                return Err("Capacity and refill rate must be positive".to_owned());
+            }
+        }
+        Ok(limits)
+    }
+
+    fn default_failed_authentication() -> Budget {
+        Budget {
+            capacity: 10,
+            refill_per_sec: 1.0,
+        }
+    }
+
+    fn budget(&self, class: Class) -> Budget {
+        match class {
+            Class::Read => self.read,
+            Class::Write => self.write,
+            Class::FailedAuthentication => self.failed_authentication,
+        }
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated_at: std::time::Instant,
+    /// Tells the entry of this bucket in [`Buckets::created`] apart from the
+    /// ones of evicted buckets of the same client.
+    created_seq: u64,
+}
+
+impl Bucket {
+    fn refill(&mut self, budget: Budget, now: std::time::Instant) {
+        let elapsed: f64 = now.duration_since(self.updated_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * budget.refill_per_sec).min(budget.capacity.into());
+        self.updated_at = now;
+    }
+
+    fn is_full(&self, budget: Budget) -> bool {
+        self.tokens >= f64::from(budget.capacity)
+    }
+}
+
+#[derive(Default)]
+struct Buckets {
+    /// Buckets by client, per class so that they're looked up by `&str`
+    /// without allocating a key.
+    by_class: std::collections::HashMap<Class, std::collections::HashMap<String, Bucket>>,
+    /// Buckets in the order they were created, for finding the oldest one
+    /// without a scan. May contain entries of buckets evicted already.
+    created: std::collections::VecDeque<(Class, String, u64)>,
+    next_seq: u64,
+}
+
+impl Buckets {
+    fn len(&self) -> usize {
+        self.by_class.values().map(|by_client| by_client.len()).sum()
+    }
+
+    fn get(&self, client: &str, class: Class) -> Option<&Bucket> {
+        self.by_class.get(&class)?.get(client)
+    }
+
+    fn get_mut(&mut self, client: &str, class: Class) -> Option<&mut Bucket> {
+        self.by_class.get_mut(&class)?.get_mut(client)
+    }
+
+    /// Bucket of the client, created full if there's none. Only creating a
+    /// bucket allocates.
+    fn get_or_create(&mut self, client: &str, class: Class, budget: Budget, now: std::time::Instant) -> &mut Bucket {
+        if self.get(client, class).is_none() {
+            if self.len() >= MAX_BUCKETS {
+                self.evict_oldest();
+            }
+            let created_seq: u64 = self.next_seq;
+            self.next_seq += 1;
+            self.created.push_back((class, client.to_owned(), created_seq));
+            self.by_class.entry(class).or_default().insert(
+                client.to_owned(),
+                Bucket {
+                    tokens: budget.capacity.into(),
+                    updated_at: now,
+                    created_seq,
+                },
+            );
+        }
+        match self.get_mut(client, class) {
+            Some(n) => n,
+            None => unreachable!("Bucket was just created"),
+        }
+    }
+
+    /// Amortised O(1), as each entry of [`Self::created`] is popped only once.
+    fn evict_oldest(&mut self) {
+        while let Some((class, client, created_seq)) = self.created.pop_front() {
+            if self
+                .get(&client, class)
+                .is_some_and(|bucket| bucket.created_seq == created_seq)
+            {
+                if let Some(by_client) = self.by_class.get_mut(&class) {
+                    by_client.remove(&client);
+                }
+                return;
+            }
+        }
+    }
+}
+
+/// Outcome of taking a token, along with the values of the `RateLimit-*`
+/// response headers.
+pub struct Decision {
+    pub allowed: bool,
+    pub limit: u32,
+    pub remaining: u32,
+    /// Seconds until the bucket is full again.
+    pub reset_secs: u64,
+    /// Seconds until the next token is available, if none is now.
+    pub retry_after_secs: u64,
+}
+
+pub struct Limiter {
+    limits: crate::reload::Watched<Limits>,
+    buckets: std::sync::Mutex<Buckets>,
+}
+
+impl Limiter {
+    /// Reads environment variable `RATE_LIMITS_FILE`: Path of the limits file,
+    /// which is reloaded when it changes. Unset by default, i.e. requests are
+    /// not limited.
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let Some(path) = std::env::var_os("RATE_LIMITS_FILE") else {
+            return Ok(None);
+        };
+
+        Ok(Some(Self {
+            limits: crate::reload::Watched::load(path.into(), Limits::parse)?,
+            buckets: std::sync::Mutex::new(Buckets::default()),
+        }))
+    }
+
+    /// Takes a token from the client's bucket of the class, if there's one.
+    pub fn check(&self, client: &str, class: Class) -> Decision {
+        let limits: std::sync::Arc<Limits> = self.limits.get();
+        let budget: Budget = limits.budget(class);
+        let now: std::time::Instant = std::time::Instant::now();
+
+        let mut buckets = self.lock_buckets();
+        let bucket: &mut Bucket = buckets.get_or_create(client, class, budget, now);
+        bucket.refill(budget, now);
+
+        let allowed: bool = bucket.tokens >= 1.0;
+        if allowed {
+            bucket.tokens -= 1.0;
+        }
+
+        Decision::new(allowed, budget, bucket.tokens)
+    }
+
+    /// Like [`Self::check`], but without taking a token, e.g. for checking the
+    /// budget before knowing whether the request counts against it.
+    pub fn peek(&self, client: &str, class: Class) -> Decision {
+        let limits: std::sync::Arc<Limits> = self.limits.get();
+        let budget: Budget = limits.budget(class);
+        let now: std::time::Instant = std::time::Instant::now();
+
+        let mut buckets = self.lock_buckets();
+        let tokens: f64 = match buckets.get_mut(client, class) {
+            Some(bucket) => {
+                bucket.refill(budget, now);
+                bucket.tokens
+            }
+            None => budget.capacity.into(),
+        };
+
+        Decision::new(tokens >= 1.0, budget, tokens)
+    }
+
+    /// Evicts buckets that have refilled completely, as they're
+    /// indistinguishable from new ones, every [`EVICTION_INTERVAL`]. Never
+    /// returns.
+    pub async fn evict_stale_periodically(&self) {
+        let mut interval: tokio::time::Interval = tokio::time::interval(EVICTION_INTERVAL);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            self.evict_stale();
+        }
+    }
+
+    fn evict_stale(&self) {
+        let limits: std::sync::Arc<Limits> = self.limits.get();
+        let now: std::time::Instant = std::time::Instant::now();
+
+        let mut buckets = self.lock_buckets();
+        for (class, by_client) in buckets.by_class.iter_mut() {
+            let budget: Budget = limits.budget(*class);
+            by_client.retain(|_, bucket| {
+                bucket.refill(budget, now);
+                !bucket.is_full(budget)
+            });
+        }
+        let Buckets { by_class, created, .. } = &mut *buckets;
+        created.retain(|(class, client, created_seq)| {
+            by_class
+                .get(class)
+                .and_then(|by_client| by_client.get(client))
+                .is_some_and(|bucket| bucket.created_seq == *created_seq)
+        });
+    }
+
+    fn lock_buckets(&self) -> std::sync::MutexGuard<'_, Buckets> {
+        match self.buckets.lock() {
+            Ok(n) => n,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+impl Decision {
+    fn new(allowed: bool, budget: Budget, tokens: f64) -> Self {
+        Self {
+            allowed,
+            limit: budget.capacity,
+            remaining: tokens.floor() as u32,
+            reset_secs: ((f64::from(budget.capacity) - tokens) / budget.refill_per_sec).ceil() as u64,
+            retry_after_secs: ((1.0 - tokens).max(0.0) / budget.refill_per_sec).ceil() as u64,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    fn limiter(limits: &str) -> (tempfile::TempDir, super::Limiter) {
+        let dir: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let path: std::path::PathBuf = dir.path().join("limits.json");
+        std::fs::write(&path, limits).unwrap();
+
+        let limiter: super::Limiter = super::Limiter {
+            limits: crate::reload::Watched::load(path, super::Limits::parse).unwrap(),
+            buckets: std::sync::Mutex::new(super::Buckets::default()),
+        };
+        (dir, limiter)
+    }
+
+    const LIMITS: &str =
+        r#"{"read":{"capacity":2,"refill_per_sec":0.001},"write":{"capacity":1,"refill_per_sec":1000}}"#;
+
+    #[test]
+    fn rejects_once_budget_is_used_up() {
+        let (_dir, limiter) = limiter(LIMITS);
+
+        assert!(limiter.check("a", super::Class::Read).allowed);
+        assert!(limiter.check("a", super::Class::Read).allowed);
+        let decision: super::Decision = limiter.check("a", super::Class::Read);
+        assert!(!decision.allowed);
+        assert_eq!(decision.remaining, 0);
+
+        assert!(limiter.check("b", super::Class::Read).allowed);
+    }
+
+    #[test]
+    fn peeking_takes_no_token_and_creates_no_bucket() {
+        let (_dir, limiter) = limiter(LIMITS);
+
+        for _ in 0..3 {
+            assert!(limiter.peek("a", super::Class::Read).allowed);
+        }
+        assert_eq!(limiter.lock_buckets().len(), 0);
+
+        limiter.check("a", super::Class::Read);
+        limiter.check("a", super::Class::Read);
+        assert!(!limiter.peek("a", super::Class::Read).allowed);
+    }
+
+    #[test]
+    fn evicts_oldest_bucket_beyond_cap() {
+        let (_dir, limiter) = limiter(LIMITS);
+
+        for i in 0..=super::MAX_BUCKETS {
+            limiter.check(&i.to_string(), super::Class::Read);
+        }
+        let buckets = limiter.lock_buckets();
+        assert_eq!(buckets.len(), super::MAX_BUCKETS);
+        assert!(buckets.get("0", super::Class::Read).is_none());
+        assert!(buckets.get("1", super::Class::Read).is_some());
+    }
+
+    #[test]
+    fn evicting_oldest_bucket_resets_its_budget() {
+        let (_dir, limiter) = limiter(LIMITS);
+
+        limiter.check("a", super::Class::Read);
+        limiter.check("a", super::Class::Read);
+        assert!(!limiter.peek("a", super::Class::Read).allowed);
+
+        /*
+         * Buckets of other classes count towards the cap, buckets evicted as
+         * stale don't.
+         */
+        limiter.check("stale", super::Class::Write);
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        limiter.evict_stale();
+        for i in 0..super::MAX_BUCKETS - 1 {
+            limiter.check(&i.to_string(), super::Class::Write);
+        }
+        assert!(!limiter.peek("a", super::Class::Read).allowed);
+
+        limiter.check("new", super::Class::Write);
+        assert!(limiter.peek("a", super::Class::Read).allowed);
+        let buckets = limiter.lock_buckets();
+        assert_eq!(buckets.len(), super::MAX_BUCKETS);
+        assert!(buckets.get("0", super::Class::Write).is_some());
+    }
+
+    #[test]
+    fn limits_failed_authentications_apart_from_requests() {
+        let (_dir, limiter) = limiter(LIMITS);
+
+        /*
+         * Not in the limits file, so the default budget of 10 applies.
+         */
+        for _ in 0..10 {
+            assert!(limiter.check("a", super::Class::FailedAuthentication).allowed);
+        }
+        let decision: super::Decision = limiter.peek("a", super::Class::FailedAuthentication);
+        assert!(!decision.allowed);
+        assert_eq!(decision.limit, 10);
+        assert_eq!(decision.retry_after_secs, 1);
+
+        assert!(limiter.check("a", super::Class::Read).allowed);
+        assert!(limiter.peek("b", super::Class::FailedAuthentication).allowed);
+    }
+
+    #[test]
+    fn evicts_stale_buckets() {
+        let (_dir, limiter) = limiter(LIMITS);
+
+        limiter.check("reader", super::Class::Read);
+        limiter.check("writer", super::Class::Write);
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        limiter.evict_stale();
+
+        /*
+         * Only the write bucket has refilled in the meantime.
+         */
+        let buckets = limiter.lock_buckets();
+        assert_eq!(buckets.len(), 1);
+        assert_eq!(buckets.created.len(), 1);
+        assert!(buckets.get("reader", super::Class::Read).is_some());
+    }
+}
@@ -0,0 +1,353 @@
+//! Optional HTTPS, terminated by the web server itself using rustls. The
+//! certificate, its private key and the CA bundle for verifying client
+//! certificates are reloaded when the files change, so that certificates can be
+//! renewed without a restart. Connections established before a reload keep
+//! using the previous certificate.
+
+/// How long a client may take to complete the TLS handshake, so that idle
+/// connections don't pile up before any request has been made.
+const HANDSHAKE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+pub struct Tls {
+    server_config: crate::reload::Watched<rustls::ServerConfig>,
+}
+
+impl Tls {
+    /// Reads the following environment variables:
+    ///
+    /// - `TLS_CERT_FILE`: Path of the PEM certificate chain, leaf first. Unset
+    ///   by default, i.e. the web server speaks plain HTTP.
+    /// - `TLS_KEY_FILE`: Path of the PEM private key of the certificate.
+    ///   Required if `TLS_CERT_FILE` is set.
+    /// - `TLS_CLIENT_CA_FILE`: Path of the PEM bundle of CA certificates that
+    ///   client certificates must be issued by. Unset by default, i.e. client
+    ///   certificates are not required (mutual TLS is disabled).
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let Some(cert_file) = std::env::var_os("TLS_CERT_FILE") else {
+            return Ok(None);
+        };
+        let Some(key_file) = std::env::var_os("TLS_KEY_FILE") else {
+            return Err("TLS_KEY_FILE is required with TLS_CERT_FILE".to_owned());
+        };
+
+        let mut paths: Vec<std::path::PathBuf> = vec![cert_file.into(), key_file.into()];
+        if let Some(client_ca_file) = std::env::var_os("TLS_CLIENT_CA_FILE") {
+            paths.push(client_ca_file.into());
+        }
+
+        Ok(Some(Self {
+            server_config: crate::reload::Watched::load_all(paths, Self::parse)?,
+        }))
+    }
+
+    /// `contents` are those of the certificate, key and optionally client CA
+    /// bundle, in that order.
+    fn parse(contents: &[Vec<u8>]) -> Result<rustls::ServerConfig, String> {
+        use rustls::pki_types::pem::PemObject;
+
+        let [cert, key, client_ca @ ..] = contents else {
+            return Err("Certificate and key are required".to_owned());
+        };
+
+        let certs: Vec<rustls::pki_types::CertificateDer<'static>> =
+            match rustls::pki_types::CertificateDer::pem_slice_iter(cert).collect() {
+                Ok(n) => n,
+                Err(err) => return Err(format!("Invalid certificate: {err}")),
+            };
+        if certs.is_empty() {
+            return Err("No certificate found".to_owned());
+        }
+        let key: rustls::pki_types::PrivateKeyDer<'static> = match rustls::pki_types::PrivateKeyDer::from_pem_slice(key)
+        {
+            Ok(n) => n,
+            Err(err) => return Err(format!("Invalid private key: {err}")),
+        };
+
+        let provider: std::sync::Arc<rustls::crypto::CryptoProvider> =
+            std::sync::Arc::new(rustls::crypto::ring::default_provider());
+        let builder = rustls::ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .map_err(|err| err.to_string())?;
+        let builder = match client_ca {
+            [] => builder.with_no_client_auth(),
+            [client_ca, ..] => {
+                let mut roots: rustls::RootCertStore = rustls::RootCertStore::empty();
+                for ca_cert in rustls::pki_types::CertificateDer::pem_slice_iter(client_ca) {
+                    let ca_cert: rustls::pki_types::CertificateDer<'static> = match ca_cert {
+                        Ok(n) => n,
+                        Err(err) => return Err(format!("Invalid client CA certificate: {err}")),
+                    };
+                    if let Err(err) = roots.add(ca_cert) {
+                        return Err(format!("Invalid client CA certificate: {err}"));
+                    }
+                }
+                let verifier = match rustls::server::WebPkiClientVerifier::builder_with_provider(
+                    std::sync::Arc::new(roots),
+                    provider,
+                )
+                .build()
+                {
+                    Ok(n) => n,
+                    Err(err) => return Err(format!("Invalid client CA bundle: {err}")),
+                };
+                builder.with_client_cert_verifier(verifier)
+            }
+        };
+
+        let mut server_config: rustls::ServerConfig = match builder.with_single_cert(certs, key) {
+            Ok(n) => n,
+            Err(err) => return Err(format!("Certificate doesn't match private key: {err}")),
+        };
+        server_config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+        Ok(server_config)
+    }
+}
+
+/// Accepts TCP connections and completes their TLS handshakes concurrently, so
+/// that a slow client can't hold up the handshakes of others. Connections
+/// whose handshake fails, e.g. for lack of a valid client certificate, are
+/// dropped.
+pub struct Listener {
+    tcp: tokio::net::TcpListener,
+    tls: std::sync::Arc<Tls>,
+    handshakes: tokio::task::JoinSet<(std::net::SocketAddr, Result<TlsStream, String>)>,
+}
+
+type TlsStream = tokio_rustls::server::TlsStream<tokio::net::TcpStream>;
+
+impl Listener {
+    pub fn new(tcp: tokio::net::TcpListener, tls: std::sync::Arc<Tls>) -> Self {
+        Self {
+            tcp,
+            tls,
+            handshakes: tokio::task::JoinSet::new(),
+        }
+    }
+}
+
+impl axum::serve::Listener for Listener {
+    type Io = TlsStream;
+    type Addr = std::net::SocketAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        loop {
+            tokio::select! {
+                accepted = self.tcp.accept() => match accepted {
+                    Ok((stream, peer)) => {
+                        let acceptor: tokio_rustls::TlsAcceptor =
+                            tokio_rustls::TlsAcceptor::from(self.tls.server_config.get());
+                        self.handshakes.spawn(async move {
+                            match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                                Ok(Ok(n)) => (peer, Ok(n)),
+                                Ok(Err(err)) => (peer, Err(err.to_string())),
+                                Err(_elapsed) => (peer, Err(format!("Not completed within {HANDSHAKE_TIMEOUT:?}"))),
+                            }
+                        });
+                    }
+                    Err(err) => {
+                        /*
+                         * E.g. out of file descriptors, which is likely to
+                         * persist for a moment. Same as axum does for plain TCP.
+                         */
+                        log::error!("Accepting connection failed: {err}");
+                        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+                    }
+                },
+                Some(joined) = self.handshakes.join_next() => match joined {
+                    Ok((peer, Ok(stream))) => return (stream, peer),
+                    Ok((peer, Err(err))) => {
+                        /*
+                         * Not a warning, as it's routine for port scanners and
+                         * for the liveness check of the systemd integration.
+                         */
+                        log::debug!("TLS handshake with {peer} failed: {err}");
+                    }
+                    Err(err) => {
+                        log::error!("{err}");
+                    }
+                },
+            }
+        }
+    }
+
+    fn local_addr(&self) -> std::io::Result<Self::Addr> {
+        self.tcp.local_addr()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    struct Ca {
+        cert: rcgen::Certificate,
+        key_pair: rcgen::KeyPair,
+    }
+
+    impl Ca {
+        fn generate() -> Self {
+            let mut params: rcgen::CertificateParams = rcgen::CertificateParams::new(Vec::<String>::new()).unwrap();
+            params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+            let key_pair: rcgen::KeyPair = rcgen::KeyPair::generate().unwrap();
+            let cert: rcgen::Certificate = params.self_signed(&key_pair).unwrap();
+            Self { cert, key_pair }
+        }
+
+        fn issue(&self, name: &str, purpose: rcgen::ExtendedKeyUsagePurpose) -> (rcgen::Certificate, rcgen::KeyPair) {
+            let mut params: rcgen::CertificateParams = rcgen::CertificateParams::new(vec![name.to_owned()]).unwrap();
+            params.extended_key_usages = vec![purpose];
+            let key_pair: rcgen::KeyPair = rcgen::KeyPair::generate().unwrap();
+            let cert: rcgen::Certificate = params.signed_by(&key_pair, &self.cert, &self.key_pair).unwrap();
+            (cert, key_pair)
+        }
+    }
+
+    /// Writes the server certificate and key, and optionally the client CA
+    /// bundle, into the directory, in the order [`super::Tls::parse`] expects.
+    fn write_files(
+        dir: &std::path::Path,
+        server: &(rcgen::Certificate, rcgen::KeyPair),
+        client_ca: Option<&Ca>,
+    ) -> Vec<std::path::PathBuf> {
+        let mut paths: Vec<std::path::PathBuf> = vec![dir.join("cert.pem"), dir.join("key.pem")];
+        std::fs::write(&paths[0], server.0.pem()).unwrap();
+        std::fs::write(&paths[1], server.1.serialize_pem()).unwrap();
+        if let Some(client_ca) = client_ca {
+            paths.push(dir.join("client_ca.pem"));
+            std::fs::write(&paths[2], client_ca.cert.pem()).unwrap();
+        }
+        paths
+    }
+
+    /// Serves nothing, but completes handshakes and hands over the accepted
+    /// connections.
+    async fn listen(
+        paths: Vec<std::path::PathBuf>,
+    ) -> (std::net::SocketAddr, tokio::sync::mpsc::Receiver<super::TlsStream>) {
+        use axum::serve::Listener;
+
+        let tls: super::Tls = super::Tls {
+            server_config: crate::reload::Watched::load_all(paths, super::Tls::parse).unwrap(),
+        };
+        let tcp: tokio::net::TcpListener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address: std::net::SocketAddr = tcp.local_addr().unwrap();
+        let mut listener: super::Listener = super::Listener::new(tcp, std::sync::Arc::new(tls));
+
+        let (tx, rx) = tokio::sync::mpsc::channel::<super::TlsStream>(8);
+        tokio::spawn(async move {
+            loop {
+                let (stream, _peer) = listener.accept().await;
+                if tx.send(stream).await.is_err() {
+                    return;
+                }
+            }
+        });
+        (address, rx)
+    }
+
+    async fn connect(
+        address: std::net::SocketAddr,
+        server_ca: &Ca,
+        client: Option<&(rcgen::Certificate, rcgen::KeyPair)>,
+    ) -> std::io::Result<tokio_rustls::client::TlsStream<tokio::net::TcpStream>> {
+        let mut roots: rustls::RootCertStore = rustls::RootCertStore::empty();
+        roots.add(server_ca.cert.der().clone()).unwrap();
+        let builder =
+            rustls::ClientConfig::builder_with_provider(std::sync::Arc::new(rustls::crypto::ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .unwrap()
+                .with_root_certificates(roots);
+        let mut client_config: rustls::ClientConfig = match client {
+            Some((cert, key_pair)) => builder
+                .with_client_auth_cert(
+                    vec![cert.der().clone()],
+                    rustls::pki_types::PrivateKeyDer::Pkcs8(key_pair.serialize_der().into()),
+                )
+                .unwrap(),
+            None => builder.with_no_client_auth(),
+        };
+        client_config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+        let connector: tokio_rustls::TlsConnector =
+            tokio_rustls::TlsConnector::from(std::sync::Arc::new(client_config));
+        let tcp: tokio::net::TcpStream = tokio::net::TcpStream::connect(address).await?;
+        connector
+            .connect(rustls::pki_types::ServerName::try_from("localhost").unwrap(), tcp)
+            .await
+    }
+
+    /// Leaf certificate the server presented on the connection.
+    fn server_cert(stream: &tokio_rustls::client::TlsStream<tokio::net::TcpStream>) -> Vec<u8> {
+        stream.get_ref().1.peer_certificates().unwrap()[0].to_vec()
+    }
+
+    #[tokio::test]
+    async fn completes_handshake() {
+        let dir: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let ca: Ca = Ca::generate();
+        let server = ca.issue("localhost", rcgen::ExtendedKeyUsagePurpose::ServerAuth);
+        let (address, mut accepted) = listen(write_files(dir.path(), &server, None)).await;
+
+        let stream = connect(address, &ca, None).await.unwrap();
+        assert_eq!(server_cert(&stream), server.0.der().to_vec());
+        assert_eq!(stream.get_ref().1.alpn_protocol(), Some(&b"http/1.1"[..]));
+        assert!(accepted.recv().await.is_some());
+    }
+
+    #[tokio::test]
+    async fn requires_client_certificate_with_client_ca() {
+        use tokio::io::AsyncReadExt;
+
+        let dir: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let ca: Ca = Ca::generate();
+        let server = ca.issue("localhost", rcgen::ExtendedKeyUsagePurpose::ServerAuth);
+        let (address, mut accepted) = listen(write_files(dir.path(), &server, Some(&ca))).await;
+
+        /*
+         * With TLS 1.3, the client considers the handshake complete before the
+         * server has verified the client, so the rejection arrives as an alert
+         * on the first read.
+         */
+        let rejected: std::io::Result<usize> = match connect(address, &ca, None).await {
+            Ok(mut stream) => stream.read(&mut [0; 1]).await,
+            Err(err) => Err(err),
+        };
+        assert!(rejected.is_err(), "{rejected:?}");
+        assert!(
+            tokio::time::timeout(std::time::Duration::from_millis(200), accepted.recv())
+                .await
+                .is_err()
+        );
+
+        let client = ca.issue("client", rcgen::ExtendedKeyUsagePurpose::ClientAuth);
+        connect(address, &ca, Some(&client)).await.unwrap();
+        assert!(accepted.recv().await.is_some());
+    }
+
+    #[tokio::test]
+    async fn reloads_changed_certificate() {
+        let dir: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let ca: Ca = Ca::generate();
+        let first = ca.issue("localhost", rcgen::ExtendedKeyUsagePurpose::ServerAuth);
+        let (address, mut accepted) = listen(write_files(dir.path(), &first, None)).await;
+        let stream = connect(address, &ca, None).await.unwrap();
+        assert_eq!(server_cert(&stream), first.0.der().to_vec());
+        accepted.recv().await.unwrap();
+
+        let second = ca.issue("localhost", rcgen::ExtendedKeyUsagePurpose::ServerAuth);
+        write_files(dir.path(), &second, None);
+
+        /*
+         * The files are checked in the background, within a poll interval.
+         */
+        let deadline: std::time::Instant = std::time::Instant::now() + 3 * crate::reload::POLL_INTERVAL;
+        loop {
+            let stream = connect(address, &ca, None).await.unwrap();
+            accepted.recv().await.unwrap();
+            if server_cert(&stream) == second.0.der().to_vec() {
+                break;
+            }
+            assert!(std::time::Instant::now() < deadline, "Certificate was not reloaded");
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+    }
+}
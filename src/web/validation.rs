@@ -0,0 +1,147 @@
+//! Validation of API input beyond what deserialization checks, so that input
+//! violating e.g. the limits of the database schema is rejected with 422
+//! Unprocessable Entity, listing what's wrong with each field, instead of
+//! failing later as 500 Internal Server Error.
+//!
+//! Nesting depth of JSON payloads is bounded by `serde_json`, which refuses
+//! to recurse deeper than 128 levels.
+
+/// Input type that is checked, and possibly normalized, after it has been
+/// deserialized.
+pub trait Validate: Sized {
+    fn validate(self) -> Result<Self, Errors>;
+}
+
+#[derive(serde::Serialize)]
+pub struct FieldError {
+    /// Name of the field in the JSON payload, or of the header.
+    pub field: &'static str,
+    pub message: String,
+}
+
+/// Response body of 422 Unprocessable Entity, e.g.:
+///
+/// ```json
+/// { "errors": [{ "field": "title", "message": "Must not be empty" }] }
+/// ```
+#[derive(Default, serde::Serialize)]
+pub struct Errors {
+    errors: Vec<FieldError>,
+}
+
+impl Errors {
+    pub fn push(&mut self, field: &'static str, message: String) {
+        self.errors.push(FieldError { field, message });
+    }
+
+    /// `Ok(value)` if no errors were pushed.
+    pub fn check<T>(self, value: T) -> Result<T, Self> {
+        if self.errors.is_empty() { Ok(value) } else { Err(self) }
+    }
+}
+
+impl std::fmt::Display for Errors {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, error) in self.errors.iter().enumerate() {
+            if i > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{}: {}", error.field, error.message)?;
+        }
+        Ok(())
+    }
+}
+
+impl axum::response::IntoResponse for Errors {
+    fn into_response(self) -> axum::response::Response {
+        (axum::http::StatusCode::UNPROCESSABLE_ENTITY, axum::Json(self)).into_response()
+    }
+}
+
+/// Normalizes text to Unicode Normalization Form C, so that the same text is
+/// always stored the same way, and trims surrounding whitespace. Fails if the
+/// result is empty, contains control characters like line breaks, or is longer
+/// than `max_chars` characters, which is how PostgreSQL counts the length of
+/// `VARCHAR(n)`.
+pub fn text(value: &str, max_chars: usize) -> Result<String, String> {
+    use unicode_normalization::UnicodeNormalization;
+
+    let normalized: String = value.nfc().collect();
+    let trimmed: &str = normalized.trim();
+
+    if trimmed.is_empty() {
+        return Err("Must not be empty".to_owned());
+    }
+    if let Some(control) = trimmed.chars().find(|c| c.is_control()) {
+        return Err(format!("Must not contain control characters, found {control:?}"));
+    }
+    let length: usize = trimmed.chars().count();
+    if length > max_chars {
+        return Err(format!("Must be at most {max_chars} characters, was {length}"));
+    }
+
+    Ok(trimmed.to_owned())
+}
+
+/// JSON request body that has been validated, see [`Validate`]. Rejections of
+/// the JSON itself are the same as those of [`axum::Json`].
+pub struct Valid<T>(pub T);
+
+impl<S, T> axum::extract::FromRequest<S> for Valid<T>
+where
+    S: Send + Sync,
+    T: Validate + serde::de::DeserializeOwned,
+{
+    type Rejection = axum::response::Response;
+
+    async fn from_request(request: axum::extract::Request, state: &S) -> Result<Self, Self::Rejection> {
+        use axum::response::IntoResponse;
+
+        let axum::Json(value) = match axum::Json::<T>::from_request(request, state).await {
+            Ok(n) => n,
+            Err(rejection) => return Err(rejection.into_response()),
+        };
+        match value.validate() {
+            Ok(n) => Ok(Self(n)),
+            Err(errors) => {
+                log::error!("Unprocessable entity: {errors}");
+                Err(errors.into_response())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn normalizes_and_trims_text() {
+        assert_eq!(super::text(" \tCafe\u{301}\n", 256), Ok("Caf\u{e9}".to_owned()));
+    }
+
+    #[test]
+    fn rejects_empty_text_or_control_characters() {
+        assert_eq!(super::text(" \n ", 256), Err("Must not be empty".to_owned()));
+        assert_eq!(
+            super::text("a\nb", 256),
+            Err("Must not contain control characters, found '\\n'".to_owned())
+        );
+        assert_eq!(
+            super::text("a\u{7f}b", 256),
+            Err("Must not contain control characters, found '\\u{7f}'".to_owned())
+        );
+    }
+
+    #[test]
+    fn counts_characters_after_normalization() {
+        /*
+         * Two bytes each in UTF-8, and two characters each before
+         * normalization.
+         */
+        assert_eq!(super::text(&"\u{e9}".repeat(256), 256), Ok("\u{e9}".repeat(256)));
+        assert_eq!(super::text(&"e\u{301}".repeat(256), 256), Ok("\u{e9}".repeat(256)));
+        assert_eq!(
+            super::text(&"\u{e9}".repeat(257), 256),
+            Err("Must be at most 256 characters, was 257".to_owned())
+        );
+    }
+}